@@ -0,0 +1,10 @@
+-- Connect Four games from day 12, so they survive restarts
+CREATE TABLE IF NOT EXISTS games (
+    id UUID PRIMARY KEY,
+    config JSONB NOT NULL,
+    grid JSONB NOT NULL,
+    status JSONB NOT NULL,
+    moves JSONB NOT NULL,
+    players JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,6 @@
+-- Finished day 12 games, for the leaderboard
+CREATE TABLE IF NOT EXISTS game_results (
+    id BIGSERIAL PRIMARY KEY,
+    result JSONB NOT NULL,
+    finished_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,9 @@
+-- Day 9 milk withdrawals and refills, so the stock survives restarts
+CREATE TABLE IF NOT EXISTS milk_ledger (
+    id BIGSERIAL PRIMARY KEY,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    kind TEXT NOT NULL,
+    client TEXT NOT NULL,
+    amount DOUBLE PRECISION NOT NULL,
+    unit TEXT NOT NULL
+);
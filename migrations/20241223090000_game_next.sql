@@ -0,0 +1,3 @@
+-- Whose turn it is in a day 12 game, which imported positions can't work
+-- out from their moves
+ALTER TABLE games ADD COLUMN IF NOT EXISTS next JSONB;
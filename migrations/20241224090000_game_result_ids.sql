@@ -0,0 +1,4 @@
+-- Which day 12 game each result came from, so undoing the winning move can
+-- take the result back out
+ALTER TABLE game_results ADD COLUMN IF NOT EXISTS game_id UUID;
+CREATE INDEX IF NOT EXISTS game_results_game_id ON game_results (game_id);
@@ -0,0 +1,5 @@
+-- Clients with API keys are recorded by a hash of the key, as the app now
+-- names them, rather than by the key itself
+UPDATE milk_ledger
+SET client = 'key:' || left(encode(sha256(convert_to(substr(client, 5), 'UTF8')), 'hex'), 16)
+WHERE client LIKE 'key:%';
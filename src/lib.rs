@@ -6,6 +6,8 @@ use axum::{
     Extension, Router,
 };
 
+pub mod rate_limit;
+
 pub mod solutions {
     pub mod day01;
     pub mod day02;
@@ -16,9 +18,54 @@ pub mod solutions {
     pub mod day19;
 }
 
+use rate_limit::{BucketConfig, RateLimitLayer};
 use solutions::*;
 
+/// How long a day 12 game may sit untouched before it is evicted.
+pub const DAY12_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How often each client may change day 19's quotes: 10 times a second, in
+/// bursts of up to 50.
+pub const DAY19_WRITE_LIMIT: BucketConfig = BucketConfig {
+    initial: 50,
+    max: 50,
+    interval_ms: 100,
+    refill_amount: 1,
+};
+
+/// How often each client may have day 16 check a signature, which is
+/// costly: twice a second, in bursts of up to 10.
+pub const DAY16_DECODE_LIMIT: BucketConfig = BucketConfig {
+    initial: 10,
+    max: 10,
+    interval_ms: 500,
+    refill_amount: 1,
+};
+
 pub fn router() -> Router {
+    router_with(
+        day09::Buckets::new(BucketConfig::default()),
+        day12::Games::new(DAY12_IDLE_TIMEOUT),
+    )
+}
+
+/// The app, with day 9 pouring milk from `buckets` and day 12 serving
+/// `games`.
+pub fn router_with(buckets: day09::Buckets, games: day12::Games) -> Router {
+    let day12_game = Router::new()
+        .route("/board", get(day12::board))
+        .route("/board/stream", get(day12::stream))
+        .route("/board/ws", get(day12::watch))
+        .route("/board/import", post(day12::import))
+        .route("/reset", post(day12::reset))
+        .route("/place/:team/:column", post(day12::place))
+        .route("/undo", post(day12::undo))
+        .route("/history", get(day12::history))
+        .route("/analysis", get(day12::analysis))
+        .route("/join/:team", post(day12::join))
+        .route("/ai/:team", post(day12::ai))
+        .route("/hint/:team", get(day12::hint));
+
     Router::new()
         .merge(
             Router::new()
@@ -39,24 +86,23 @@ pub fn router() -> Router {
             Router::new()
                 .route("/milk", post(day09::milk))
                 .route("/refill", post(day09::refill))
-                .layer(Extension(Arc::new(tokio::sync::RwLock::new(
-                    leaky_bucket::RateLimiter::builder()
-                        .initial(5)
-                        .max(5)
-                        .interval(Duration::from_millis(1_000))
-                        .build(),
-                )))),
+                .route("/config", get(day09::config))
+                .route("/status", get(day09::status))
+                .route("/status/stream", get(day09::status_stream))
+                .route("/ledger", get(day09::ledger))
+                .route("/inventory", get(day09::inventory))
+                .layer(Extension(Arc::new(tokio::sync::RwLock::new(buckets)))),
         )
         .nest(
             "/12",
             Router::new()
-                .route("/board", get(day12::board))
-                .route("/reset", post(day12::reset))
-                .route("/place/:team/:column", post(day12::place))
+                .merge(day12_game.clone())
+                .nest("/games/:id", day12_game)
+                .route("/games", post(day12::create_game))
+                .route("/replay", post(day12::replay))
+                .route("/stats", get(day12::stats))
                 .route("/random-board", get(day12::random_board))
-                .layer(Extension(Arc::new(tokio::sync::RwLock::new(
-                    day12::Board::default(),
-                ))))
+                .layer(Extension(Arc::new(tokio::sync::RwLock::new(games))))
                 .layer(Extension(Arc::new(tokio::sync::RwLock::new(
                     <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(2024),
                 )))),
@@ -66,16 +112,20 @@ pub fn router() -> Router {
             Router::new()
                 .route("/wrap", post(day16::wrap))
                 .route("/unwrap", get(day16::unwrap))
-                .route("/decode", post(day16::decode)),
+                .route(
+                    "/decode",
+                    post(day16::decode).layer(RateLimitLayer::new(DAY16_DECODE_LIMIT)),
+                ),
         )
         .nest(
             "/19",
             Router::new()
                 .route("/reset", post(day19::reset))
-                .route("/cite/:id", get(day19::cite))
                 .route("/remove/:id", delete(day19::remove))
                 .route("/undo/:id", put(day19::undo))
                 .route("/draft", post(day19::draft))
+                .route_layer(RateLimitLayer::new(DAY19_WRITE_LIMIT))
+                .route("/cite/:id", get(day19::cite))
                 .route("/list", get(day19::list)),
         )
 }
@@ -1,13 +1,62 @@
-use shuttlings_cch24::router;
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{Extension, Router};
+use shuttle_runtime::{CustomError, Error};
+use shuttlings_cch24::{
+    rate_limit::{BucketConfig, Clients},
+    router_with,
+    solutions::{day09, day12},
+    DAY12_IDLE_TIMEOUT,
+};
+
+/// Serves the app with each connection's address available as
+/// `ConnectInfo`, for telling clients apart when no proxy says who they are.
+struct Server(Router);
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for Server {
+    async fn bind(self, addr: SocketAddr) -> Result<(), Error> {
+        let listener = shuttle_runtime::tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(CustomError::new)?;
+        axum::serve(
+            listener,
+            self.0.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(CustomError::new)?;
+
+        Ok(())
+    }
+}
 
 #[shuttle_runtime::main]
-async fn main(#[shuttle_shared_db::Postgres] pool: sqlx::PgPool) -> shuttle_axum::ShuttleAxum {
+async fn main(#[shuttle_shared_db::Postgres] pool: sqlx::PgPool) -> Result<Server, Error> {
     sqlx::migrate!()
         .run(&pool)
         .await
         .expect("Could not migrate DB");
 
-    let router = router().layer(axum::Extension(pool));
+    let games = day12::Games::restore(pool.clone(), DAY12_IDLE_TIMEOUT)
+        .await
+        .expect("Could not load games");
+
+    // A ledger only pours milk that's been restocked, so keeping one is opt-in
+    let buckets = if std::env::var("MILK_LEDGER").is_ok_and(|ledger| ledger == "true") {
+        day09::Buckets::restore(pool.clone(), BucketConfig::default())
+            .await
+            .expect("Could not load milk ledger")
+    } else {
+        day09::Buckets::new(BucketConfig::default())
+    };
+    // So is capping withdrawals across all clients
+    let cap = day09::Buckets::cap_from_env()
+        .map_err(|message| CustomError::msg(format!("Invalid MILK_CAP: {}", message.trim())))?;
+    let buckets = buckets.with_cap(cap);
+
+    let router = router_with(buckets, games)
+        .layer(Extension(pool))
+        .layer(Extension(Arc::new(Clients::from_env())));
 
-    Ok(router.into())
+    Ok(Server(router))
 }
@@ -0,0 +1,560 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    fmt::Display,
+    hash::Hash,
+    net::{AddrParseError, IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Request},
+    http::{request::Parts, Extensions, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+
+mod bucket;
+
+pub use bucket::{Bucket, BucketConfig, Quota};
+
+/// Who a request is charged to: the holder of a known API key if one is
+/// sent, or else the caller's address, as forwarded by a trusted proxy or as
+/// connected. See [`Clients`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Client {
+    /// A hash of the key, so clients can be shown and recorded without
+    /// giving their key away. See [`Client::api_key`].
+    ApiKey(String),
+    Ip(IpAddr),
+    /// Callers with neither, who share a bucket.
+    Anonymous,
+}
+
+impl Display for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Client::ApiKey(key) => write!(f, "key:{key}"),
+            Client::Ip(ip) => write!(f, "{ip}"),
+            Client::Anonymous => write!(f, "anonymous"),
+        }
+    }
+}
+
+impl Client {
+    /// The holder of API key `key`.
+    pub fn api_key(key: &str) -> Self {
+        let hash = Sha256::digest(key.as_bytes());
+        Client::ApiKey(hash[..8].iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+}
+
+impl FromStr for Client {
+    type Err = AddrParseError;
+
+    /// Reads a client back from how it's displayed.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text == "anonymous" {
+            return Ok(Client::Anonymous);
+        }
+        match text.strip_prefix("key:") {
+            Some(key) => Ok(Client::ApiKey(key.to_string())),
+            None => text.parse().map(Client::Ip),
+        }
+    }
+}
+
+const API_KEY: &str = "x-api-key";
+const FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// How clients are told apart: the API keys handed out, and how many proxies
+/// in front of the app append to `X-Forwarded-For`. Callers control every
+/// address left of the ones those proxies added, so only the address the
+/// outermost trusted proxy saw is used.
+///
+/// Add it to the app as an `Extension(Arc<Clients>)`; without one, no API
+/// keys are known and one proxy is trusted.
+#[derive(Debug, Clone)]
+pub struct Clients {
+    api_keys: HashSet<String>,
+    trusted_proxies: usize,
+}
+
+impl Default for Clients {
+    fn default() -> Self {
+        Self {
+            api_keys: HashSet::new(),
+            trusted_proxies: 1,
+        }
+    }
+}
+
+impl Clients {
+    pub fn new(api_keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            api_keys: api_keys.into_iter().map(Into::into).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Trusts the last `trusted_proxies` hops of `X-Forwarded-For`. With
+    /// none, the header is ignored and only the connected address is used.
+    pub fn trusted_proxies(self, trusted_proxies: usize) -> Self {
+        Self {
+            trusted_proxies,
+            ..self
+        }
+    }
+
+    /// Known keys from the comma-separated `API_KEYS` variable, and the
+    /// number of proxies from `TRUSTED_PROXIES`, if set.
+    pub fn from_env() -> Self {
+        let api_keys = std::env::var("API_KEYS").unwrap_or_default();
+        let clients = Self::new(
+            api_keys
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty()),
+        );
+        match std::env::var("TRUSTED_PROXIES").map(|proxies| proxies.parse()) {
+            Ok(Ok(proxies)) => clients.trusted_proxies(proxies),
+            _ => clients,
+        }
+    }
+
+    /// The client sending a request with `headers` over a connection from
+    /// `connected`. Unknown API keys are ignored.
+    pub fn identify(&self, headers: &HeaderMap, connected: Option<IpAddr>) -> Client {
+        let header = |name: &str| headers.get(name).and_then(|header| header.to_str().ok());
+
+        if let Some(key) = header(API_KEY).filter(|key| self.api_keys.contains(*key)) {
+            return Client::api_key(key);
+        }
+
+        // Each proxy appends the address it was connected from
+        let forwarded = self
+            .trusted_proxies
+            .checked_sub(1)
+            .and_then(|hop| {
+                let hops = headers
+                    .get_all(FORWARDED_FOR)
+                    .iter()
+                    .filter_map(|header| header.to_str().ok())
+                    .flat_map(|header| header.split(','))
+                    .collect::<Vec<_>>();
+                hops.into_iter().rev().nth(hop)
+            })
+            .and_then(|ip| ip.trim().parse().ok());
+
+        forwarded
+            .or(connected)
+            .map_or(Client::Anonymous, Client::Ip)
+    }
+}
+
+impl Client {
+    /// The client sending a request with these headers and extensions,
+    /// told apart by the [`Clients`] extension.
+    pub fn of(headers: &HeaderMap, extensions: &Extensions) -> Self {
+        let connected = extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        match extensions.get::<Arc<Clients>>() {
+            Some(clients) => clients.identify(headers, connected),
+            None => Clients::default().identify(headers, connected),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Client::of(&parts.headers, &parts.extensions))
+    }
+}
+
+/// A bucket for each key, created on first use and dropped once it's been
+/// idle for `idle_timeout`, or for as long as it takes a bucket to fill up if
+/// that's longer.
+pub struct KeyedBuckets<K> {
+    config: BucketConfig,
+    buckets: HashMap<K, (Arc<Bucket>, Instant)>,
+    idle_timeout: Duration,
+}
+
+impl<K: Hash + Eq + Clone> KeyedBuckets<K> {
+    pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+    pub fn new(config: BucketConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+            idle_timeout: Self::IDLE_TIMEOUT,
+        }
+    }
+
+    pub fn config(&self) -> BucketConfig {
+        self.config
+    }
+
+    /// `key`'s bucket, marked as just used.
+    pub fn bucket(&mut self, key: &K) -> Arc<Bucket> {
+        self.evict_idle();
+
+        let now = Instant::now();
+        let (bucket, last_used) = self
+            .buckets
+            .entry(key.clone())
+            .or_insert_with(|| (Arc::new(Bucket::new(self.config)), now));
+        *last_used = now;
+
+        bucket.clone()
+    }
+
+    /// `key`'s bucket, if it has one.
+    pub fn get(&self, key: &K) -> Option<Arc<Bucket>> {
+        self.buckets.get(key).map(|(bucket, _)| bucket.clone())
+    }
+
+    /// Every bucket that hasn't been dropped for going idle.
+    pub fn buckets(&self) -> impl Iterator<Item = &Arc<Bucket>> {
+        self.buckets.values().map(|(bucket, _)| bucket)
+    }
+
+    /// What `key` may still take.
+    pub fn quota(&self, key: &K) -> Quota {
+        match self.buckets.get(key) {
+            Some((bucket, _)) => bucket.quota(),
+            None => Bucket::new(self.config).quota(),
+        }
+    }
+
+    /// Drops every bucket, so each key starts over with a full one built
+    /// from `config`.
+    pub fn reset(&mut self, config: BucketConfig) {
+        self.config = config;
+        self.buckets.clear();
+    }
+
+    fn evict_idle(&mut self) {
+        // A bucket dropped before it filled up would hand its key extra tokens
+        let idle_timeout = self.idle_timeout.max(self.config.fill_time());
+        self.buckets
+            .retain(|_, (_, last_used)| last_used.elapsed() < idle_timeout);
+    }
+}
+
+/// Picks the bucket a request is charged to.
+pub trait KeyStrategy: Clone + Send + Sync + 'static {
+    type Key: Hash + Eq + Clone + Send + 'static;
+
+    fn key(&self, request: &Request) -> Self::Key;
+}
+
+/// A bucket for each [`Client`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PerClient;
+
+impl KeyStrategy for PerClient {
+    type Key = Client;
+
+    fn key(&self, request: &Request) -> Client {
+        Client::of(request.headers(), request.extensions())
+    }
+}
+
+/// One bucket for everyone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Shared;
+
+impl KeyStrategy for Shared {
+    type Key = ();
+
+    fn key(&self, _request: &Request) {}
+}
+
+/// The response to a request turned away, given the quota it ran out of.
+pub trait Reject: Clone + Send + Sync + 'static {
+    fn reject(&self, quota: Quota) -> Response;
+}
+
+impl<F> Reject for F
+where
+    F: Fn(Quota) -> Response + Clone + Send + Sync + 'static,
+{
+    fn reject(&self, quota: Quota) -> Response {
+        self(quota)
+    }
+}
+
+/// `429 Too Many Requests`, with the quota headers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TooManyRequests;
+
+impl Reject for TooManyRequests {
+    fn reject(&self, quota: Quota) -> Response {
+        (StatusCode::TOO_MANY_REQUESTS, quota, "Too many requests\n").into_response()
+    }
+}
+
+/// Rate limits the routes it wraps, charging each request `cost` tokens from
+/// the bucket its [`KeyStrategy`] picks. Requests that can't pay get the
+/// [`Reject`] response instead; the rest get the quota headers added.
+///
+/// Clones share their buckets, so routes can draw on one budget at
+/// different costs.
+#[derive(Clone)]
+pub struct RateLimitLayer<S: KeyStrategy = PerClient, R = TooManyRequests> {
+    buckets: Arc<Mutex<KeyedBuckets<S::Key>>>,
+    key: S,
+    cost: usize,
+    reject: R,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Buckets are left consistent by a panic mid-request
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl RateLimitLayer {
+    /// Charges each client 1 token per request from a bucket of their own.
+    pub fn new(config: BucketConfig) -> Self {
+        Self {
+            buckets: Arc::new(Mutex::new(KeyedBuckets::new(config))),
+            key: PerClient,
+            cost: 1,
+            reject: TooManyRequests,
+        }
+    }
+}
+
+impl<S: KeyStrategy, R: Reject> RateLimitLayer<S, R> {
+    pub fn cost(self, cost: usize) -> Self {
+        Self { cost, ..self }
+    }
+
+    /// Charges requests to the buckets `key` picks. These are new buckets,
+    /// not shared with clones of this layer.
+    pub fn key<T: KeyStrategy>(self, key: T) -> RateLimitLayer<T, R> {
+        let config = lock(&self.buckets).config();
+        RateLimitLayer {
+            buckets: Arc::new(Mutex::new(KeyedBuckets::new(config))),
+            key,
+            cost: self.cost,
+            reject: self.reject,
+        }
+    }
+
+    pub fn reject<T: Reject>(self, reject: T) -> RateLimitLayer<S, T> {
+        RateLimitLayer {
+            buckets: self.buckets,
+            key: self.key,
+            cost: self.cost,
+            reject,
+        }
+    }
+}
+
+impl<Svc, S: KeyStrategy, R: Reject> Layer<Svc> for RateLimitLayer<S, R> {
+    type Service = RateLimit<Svc, S, R>;
+
+    fn layer(&self, inner: Svc) -> Self::Service {
+        RateLimit {
+            inner,
+            limit: self.clone(),
+        }
+    }
+}
+
+/// A service rate limited by a [`RateLimitLayer`].
+#[derive(Clone)]
+pub struct RateLimit<Svc, S: KeyStrategy, R> {
+    inner: Svc,
+    limit: RateLimitLayer<S, R>,
+}
+
+impl<Svc, S, R> Service<Request> for RateLimit<Svc, S, R>
+where
+    Svc: Service<Request>,
+    Svc::Response: IntoResponse,
+    Svc::Future: Send + 'static,
+    S: KeyStrategy,
+    R: Reject,
+{
+    type Response = Response;
+    type Error = Svc::Error;
+    type Future = BoxFuture<'static, Result<Response, Svc::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let key = self.limit.key.key(&request);
+        let bucket = lock(&self.limit.buckets).bucket(&key);
+
+        if !bucket.try_acquire(self.limit.cost) {
+            let rejection = self.limit.reject.reject(bucket.quota());
+            return Box::pin(async { Ok(rejection) });
+        }
+
+        let quota = bucket.quota();
+        let response = self.inner.call(request);
+        Box::pin(async move { Ok((quota, response.await?).into_response()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BucketConfig, Client, Clients, Quota, RateLimitLayer, Shared};
+    use crate::{router, test_utils::collect_body, DAY16_DECODE_LIMIT};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        response::{IntoResponse, Response},
+        routing::post,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    const TWO: BucketConfig = BucketConfig {
+        initial: 2,
+        max: 2,
+        interval_ms: 60_000,
+        refill_amount: 1,
+    };
+
+    async fn send(app: &Router, uri: &str, ip: &str) -> Response {
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .method("POST")
+                    .header("X-Forwarded-For", ip)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn per_client() {
+        let app = Router::new()
+            .route("/write", post(|| async { "Written\n" }))
+            .route_layer(RateLimitLayer::new(TWO));
+
+        let response = send(&app, "/write", "192.0.2.1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-remaining"], "1");
+        assert_eq!(collect_body(response).await, "Written\n");
+
+        assert_eq!(
+            send(&app, "/write", "192.0.2.1").await.status(),
+            StatusCode::OK
+        );
+        let response = send(&app, "/write", "192.0.2.1").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "60");
+
+        assert_eq!(
+            send(&app, "/write", "192.0.2.2").await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_costs() {
+        let limit = RateLimitLayer::new(TWO).key(Shared);
+        let app = Router::new()
+            .route("/cheap", post(|| async {}).layer(limit.clone()))
+            .route("/dear", post(|| async {}).layer(limit.cost(2)));
+
+        assert_eq!(
+            send(&app, "/cheap", "192.0.2.1").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&app, "/dear", "192.0.2.2").await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            send(&app, "/cheap", "192.0.2.3").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&app, "/cheap", "192.0.2.4").await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn custom_rejection() {
+        let app = Router::new()
+            .route("/write", post(|| async {}))
+            .route_layer(RateLimitLayer::new(TWO).cost(3).reject(|quota: Quota| {
+                let message = format!("Only {} left\n", quota.remaining);
+                (StatusCode::SERVICE_UNAVAILABLE, message).into_response()
+            }));
+
+        let response = send(&app, "/write", "192.0.2.1").await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(collect_body(response).await, "Only 2 left\n");
+    }
+
+    #[tokio::test]
+    async fn limited_routes() {
+        let app = router();
+        for _ in 0..DAY16_DECODE_LIMIT.max {
+            let response = send(&app, "/16/decode", "192.0.2.1").await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        let response = send(&app, "/16/decode", "192.0.2.1").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let response = send(&app, "/16/decode", "192.0.2.2").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn identify() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut request = Request::builder();
+            for (name, value) in pairs {
+                request = request.header(*name, *value);
+            }
+            request.body(()).unwrap().into_parts().0.headers
+        };
+        let ip = |ip: &str| Client::Ip(ip.parse().unwrap());
+        let clients = Clients::new(["known"]);
+
+        let known = headers(&[("X-Api-Key", "known")]);
+        assert_eq!(clients.identify(&known, None), Client::api_key("known"));
+
+        let spoofed = headers(&[
+            ("X-Api-Key", "made up"),
+            ("X-Forwarded-For", "203.0.113.7, 198.51.100.1"),
+        ]);
+        assert_eq!(clients.identify(&spoofed, None), ip("198.51.100.1"));
+        assert_eq!(
+            clients.clone().trusted_proxies(2).identify(&spoofed, None),
+            ip("203.0.113.7")
+        );
+        let connected = Some("192.0.2.1".parse().unwrap());
+        assert_eq!(
+            clients.trusted_proxies(0).identify(&spoofed, connected),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            Clients::default().identify(&headers(&[]), None),
+            Client::Anonymous
+        );
+    }
+}
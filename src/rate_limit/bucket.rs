@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use axum::{
+    http::HeaderValue,
+    response::{IntoResponseParts, ResponseParts},
+};
+use leaky_bucket::RateLimiter;
+use tokio::time::Instant;
+
+/// Size and refill rate of a bucket: it starts with `initial` tokens and
+/// gains `refill_amount` every `interval_ms`, up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct BucketConfig {
+    pub initial: usize,
+    pub max: usize,
+    pub interval_ms: u64,
+    pub refill_amount: usize,
+}
+
+impl Default for BucketConfig {
+    fn default() -> Self {
+        Self {
+            initial: 5,
+            max: 5,
+            interval_ms: 1_000,
+            refill_amount: 1,
+        }
+    }
+}
+
+impl BucketConfig {
+    /// The most tokens a bucket may hold.
+    pub const MAX_TOKENS: usize = 1_000_000;
+    /// The longest a bucket may go between refills: a day.
+    pub const MAX_INTERVAL_MS: u64 = 86_400_000;
+
+    fn build(&self) -> RateLimiter {
+        RateLimiter::builder()
+            .initial(self.initial)
+            .max(self.max)
+            .interval(self.interval())
+            .refill(self.refill_amount)
+            .build()
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    /// How long an empty bucket takes to fill up.
+    pub fn fill_time(&self) -> Duration {
+        let refills = self.max.div_ceil(self.refill_amount);
+        self.interval()
+            .saturating_mul(u32::try_from(refills).unwrap_or(u32::MAX))
+    }
+
+    /// Checks that a bucket can be built from this config.
+    pub fn validate(self) -> Result<Self, &'static str> {
+        if self.max == 0 || self.interval_ms == 0 || self.refill_amount == 0 {
+            return Err("max, interval_ms and refill_amount must be positive\n");
+        }
+        if self.initial > self.max {
+            return Err("initial can't be more than max\n");
+        }
+        if self.max > Self::MAX_TOKENS || self.interval_ms > Self::MAX_INTERVAL_MS {
+            return Err("max can be at most 1000000, and interval_ms at most a day\n");
+        }
+        Ok(self)
+    }
+}
+
+/// A rate limiter that can also report how full it is and when it refills,
+/// which [`RateLimiter`] keeps to itself.
+pub struct Bucket {
+    limiter: RateLimiter,
+    config: BucketConfig,
+    /// Refills happen a whole number of intervals after this.
+    created: Instant,
+}
+
+impl Bucket {
+    pub fn new(config: BucketConfig) -> Self {
+        Self {
+            limiter: config.build(),
+            config,
+            created: Instant::now(),
+        }
+    }
+
+    pub fn config(&self) -> BucketConfig {
+        self.config
+    }
+
+    pub fn try_acquire(&self, amount: usize) -> bool {
+        self.limiter.try_acquire(amount)
+    }
+
+    /// Tokens that can be taken right now.
+    pub fn remaining(&self) -> usize {
+        // The limiter only counts refills when asked for more than it has,
+        // so ask for more than it can ever hold.
+        self.limiter
+            .try_acquire(self.limiter.max().saturating_add(1));
+        self.limiter.balance()
+    }
+
+    /// Time until the next refill.
+    pub fn next_refill(&self) -> Duration {
+        let interval = self.config.interval().as_millis().max(1);
+        let since = self.created.elapsed().as_millis() % interval;
+        Duration::from_millis(u64::try_from(interval - since).unwrap_or(u64::MAX))
+    }
+
+    /// Time until `amount` can be taken, if nobody else takes any first.
+    pub fn time_until(&self, amount: usize) -> Duration {
+        let missing = amount.saturating_sub(self.remaining());
+        if missing == 0 {
+            return Duration::ZERO;
+        }
+
+        let refills = missing.div_ceil(self.config.refill_amount);
+        let later = u32::try_from(refills - 1).unwrap_or(u32::MAX);
+        self.next_refill()
+            .saturating_add(self.config.interval().saturating_mul(later))
+    }
+
+    pub fn quota(&self) -> Quota {
+        Quota {
+            limit: self.config.max,
+            remaining: self.remaining(),
+            retry_after: self.time_until(1),
+            reset: self.time_until(self.config.max),
+        }
+    }
+}
+
+/// A client's standing with the rate limiter, sent back as `RateLimit-*`
+/// and `Retry-After` headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub limit: usize,
+    pub remaining: usize,
+    /// Until there's a token to take.
+    pub retry_after: Duration,
+    /// Until the bucket is full again.
+    pub reset: Duration,
+}
+
+impl Quota {
+    /// The tighter of two quotas that both apply.
+    pub fn min(self, other: Self) -> Self {
+        Self {
+            limit: self.limit.min(other.limit),
+            remaining: self.remaining.min(other.remaining),
+            retry_after: self.retry_after.max(other.retry_after),
+            reset: self.reset.max(other.reset),
+        }
+    }
+}
+
+/// Whole seconds, rounded up so clients don't retry too early.
+fn seconds(duration: Duration) -> HeaderValue {
+    HeaderValue::from(duration.as_millis().div_ceil(1_000) as u64)
+}
+
+impl IntoResponseParts for Quota {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        let headers = res.headers_mut();
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", seconds(self.reset));
+        headers.insert("retry-after", seconds(self.retry_after));
+        Ok(res)
+    }
+}
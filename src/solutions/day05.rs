@@ -1,66 +1,420 @@
+use std::{fmt, ops::Range};
+
 use axum::{
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
+    extract::{FromRequest, Multipart, Query, Request},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
 };
-use cargo_manifest::Manifest;
+use cargo_manifest::{Manifest, MaybeInherited};
 use itertools::Itertools;
+use serde::{
+    de::{self, IgnoredAny, MapAccess, SeqAccess},
+    Deserialize, Deserializer,
+};
+use toml::Spanned;
 
-#[derive(Debug, serde::Deserialize)]
-struct Orders {
-    orders: Vec<toml::Value>,
-}
+const MAGIC_KEYWORD: &str = "Christmas 2024";
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct Order {
     item: String,
     quantity: u32,
 }
 
-pub async fn manifest(headers: HeaderMap, body: String) -> impl IntoResponse {
-    let Some(package) = match headers
-        .get("Content-Type")
-        .and_then(|header| header.to_str().ok())
-    {
-        Some("application/toml") => toml::from_str::<Manifest>(&body).ok(),
-        Some("application/yaml") => serde_yaml::from_str::<Manifest>(&body).ok(),
-        Some("application/json") => serde_json::from_str::<Manifest>(&body).ok(),
-        _ => return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response(),
+/// Where the orders in a TOML manifest are, as byte ranges of the source.
+#[derive(Debug, Default, serde::Deserialize)]
+struct OrderSpans {
+    package: Option<MetadataSpans>,
+    workspace: Option<MetadataSpans>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct MetadataSpans {
+    metadata: Option<OrdersSpans>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct OrdersSpans {
+    orders: Option<Spanned<ListSpans>>,
+}
+
+/// Where each order starts, or nothing when `orders` isn't a list.
+#[derive(Debug, Default)]
+struct ListSpans(Vec<Spanned<IgnoredAny>>);
+
+impl<'de> Deserialize<'de> for ListSpans {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = ListSpans;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("any value")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ListSpans, A::Error> {
+                let mut spans = Vec::new();
+                while let Some(span) = seq.next_element()? {
+                    spans.push(span);
+                }
+                Ok(ListSpans(spans))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ListSpans, A::Error> {
+                while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                Ok(ListSpans::default())
+            }
+
+            fn visit_bool<E>(self, _: bool) -> Result<ListSpans, E> {
+                Ok(ListSpans::default())
+            }
+
+            fn visit_i64<E>(self, _: i64) -> Result<ListSpans, E> {
+                Ok(ListSpans::default())
+            }
+
+            fn visit_u64<E>(self, _: u64) -> Result<ListSpans, E> {
+                Ok(ListSpans::default())
+            }
+
+            fn visit_f64<E>(self, _: f64) -> Result<ListSpans, E> {
+                Ok(ListSpans::default())
+            }
+
+            fn visit_str<E>(self, _: &str) -> Result<ListSpans, E> {
+                Ok(ListSpans::default())
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
     }
-    .and_then(|man| man.package) else {
-        return (StatusCode::BAD_REQUEST, "Invalid manifest").into_response();
+}
+
+/// Line and column numbers, counting from 1, of where the `orders` in a
+/// `[package.metadata]` or `[workspace.metadata]` table start, and of where
+/// each order in them does.
+#[derive(Debug, Clone, Default)]
+struct Positions {
+    list: Option<(usize, usize)>,
+    orders: Vec<(usize, usize)>,
+}
+
+fn positions(text: &str, spans: Option<MetadataSpans>) -> Positions {
+    let position = |Range { start, .. }| {
+        let before = &text[..start];
+        let line = before.matches('\n').count() + 1;
+        let column = before[before.rfind('\n').map_or(0, |newline| newline + 1)..]
+            .chars()
+            .count()
+            + 1;
+        (line, column)
     };
 
-    if !package
-        .keywords
-        .and_then(|keys| {
-            keys.as_local()
-                .map(|keys| keys.iter().any(|key| key == "Christmas 2024"))
+    let Some(list) = spans.and_then(|spans| spans.metadata?.orders) else {
+        return Positions::default();
+    };
+    Positions {
+        list: Some(position(list.span())),
+        orders: list
+            .get_ref()
+            .0
+            .iter()
+            .map(|order| position(order.span()))
+            .collect(),
+    }
+}
+
+/// A manifest, and where its orders are in the source when the format
+/// keeps track of that.
+struct Source {
+    manifest: Manifest,
+    package_positions: Positions,
+    workspace_positions: Positions,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    fn of_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "application/toml" => Some(Format::Toml),
+            "application/yaml" => Some(Format::Yaml),
+            "application/json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// The format of an uploaded file, going by its extension.
+    fn of_file_name(name: &str) -> Option<Self> {
+        match name.rsplit_once('.')?.1 {
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// A manifest for a package, a workspace, or both.
+    fn parse(self, text: &str) -> Option<Source> {
+        let manifest = match self {
+            Format::Toml => toml::from_str::<Manifest>(text).ok(),
+            Format::Yaml => serde_yaml::from_str::<Manifest>(text).ok(),
+            Format::Json => serde_json::from_str::<Manifest>(text).ok(),
+        }?;
+        if manifest.package.is_none() && manifest.workspace.is_none() {
+            return None;
+        }
+
+        // Only the TOML parser can tell where values came from
+        let spans = match self {
+            Format::Toml => toml::from_str::<OrderSpans>(text).unwrap_or_default(),
+            Format::Yaml | Format::Json => OrderSpans::default(),
+        };
+        Some(Source {
+            manifest,
+            package_positions: positions(text, spans.package),
+            workspace_positions: positions(text, spans.workspace),
         })
-        .unwrap_or(false)
-    {
-        return (StatusCode::BAD_REQUEST, "Magic keyword not provided").into_response();
     }
+}
 
-    let orders = match package
-        .metadata
-        .and_then(|meta| meta.try_into::<Orders>().ok())
-    {
-        Some(Orders { orders }) => orders
+fn invalid() -> Response {
+    (StatusCode::BAD_REQUEST, "Invalid manifest").into_response()
+}
+
+/// The manifests uploaded as `multipart/form-data`, one per part. Each part's
+/// format comes from its content type, or else its file name.
+async fn upload(mut multipart: Multipart) -> Result<Vec<Source>, Response> {
+    let mut sources = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(|_| invalid())? {
+        let format = field
+            .content_type()
+            .and_then(Format::of_content_type)
+            .or_else(|| field.file_name().and_then(Format::of_file_name))
+            .ok_or_else(|| StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response())?;
+        let text = field.text().await.map_err(|_| invalid())?;
+        sources.push(format.parse(&text).ok_or_else(invalid)?);
+    }
+
+    if sources.is_empty() {
+        return Err(invalid());
+    }
+    Ok(sources)
+}
+
+fn has_magic_keyword(keywords: Option<&[String]>) -> bool {
+    keywords.is_some_and(|keywords| keywords.iter().any(|key| key == MAGIC_KEYWORD))
+}
+
+/// The orders in a package, or in the workspace as a whole: each one either
+/// read or turned down with a reason.
+struct Group {
+    name: String,
+    orders: Vec<Result<Order, String>>,
+    /// Why `orders` couldn't be read as a list, if it couldn't.
+    malformed: Option<String>,
+    positions: Positions,
+}
+
+impl Group {
+    /// The orders in a `[package.metadata]` or `[workspace.metadata]` table.
+    fn new(name: String, metadata: Option<toml::Value>, positions: Positions) -> Self {
+        let orders = metadata
+            .as_ref()
+            .and_then(|meta| meta.get("orders"))
+            .map(|orders| orders.clone().try_into::<Vec<toml::Value>>())
+            .transpose()
+            .map_err(|error| error.message().to_string());
+        let (orders, malformed) = match orders {
+            Ok(orders) => (orders.unwrap_or_default(), None),
+            Err(error) => (Vec::new(), Some(error)),
+        };
+        let orders = orders
             .into_iter()
-            .filter_map(|order| order.try_into::<Order>().ok())
-            .collect::<Vec<_>>(),
-        _ => return StatusCode::NO_CONTENT.into_response(),
+            .map(|order| {
+                order
+                    .try_into::<Order>()
+                    .map_err(|error| error.message().to_string())
+            })
+            .collect();
+
+        Self {
+            name,
+            orders,
+            malformed,
+            positions,
+        }
+    }
+
+    fn valid(&self) -> impl Iterator<Item = &Order> {
+        self.orders.iter().filter_map(|order| order.as_ref().ok())
+    }
+
+    fn list(&self) -> String {
+        self.valid()
+            .map(|order| format!("{}: {}", order.item, order.quantity))
+            .join("\n")
+    }
+
+    fn report(&self) -> impl Iterator<Item = Checked<'_>> {
+        let malformed = self.malformed.as_deref().map(|error| {
+            let (line, column) = self.positions.list.unzip();
+            Checked {
+                package: &self.name,
+                index: None,
+                order: None,
+                error: Some(error),
+                line,
+                column,
+            }
+        });
+        let orders = self.orders.iter().enumerate().map(|(index, order)| {
+            let (line, column) = self.positions.orders.get(index).copied().unzip();
+            Checked {
+                package: &self.name,
+                index: Some(index),
+                order: order.as_ref().ok(),
+                error: order.as_ref().err().map(String::as_str),
+                line,
+                column,
+            }
+        });
+        malformed.into_iter().chain(orders)
+    }
+}
+
+/// An order in a validation report, or the `orders` of a package when they
+/// aren't a list, in which case there's no index.
+#[derive(Debug, serde::Serialize)]
+struct Checked<'a> {
+    package: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<&'a Order>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct ManifestQuery {
+    strict: bool,
+}
+
+/// Lists the gift orders in a manifest. Several manifests can be uploaded
+/// together as `multipart/form-data`, such as a workspace root and its
+/// members, in which case orders are listed under the package they're for,
+/// or under `[workspace]` for those in `[workspace.metadata]`. Members
+/// inheriting their keywords get the workspace's.
+///
+/// Orders that can't be read are left out, unless `?strict=true` asks for a
+/// JSON report on every order instead: what it was read as or why it
+/// couldn't be, and where it is in a TOML manifest. `orders` that aren't a
+/// list get an entry of their own.
+pub async fn manifest(request: Request) -> Response {
+    let Ok(Query(ManifestQuery { strict })) = Query::try_from_uri(request.uri()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid query").into_response();
+    };
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
+        .unwrap_or_default();
+
+    let sources = if content_type.starts_with("multipart/form-data") {
+        let Ok(multipart) = Multipart::from_request(request, &()).await else {
+            return invalid();
+        };
+        match upload(multipart).await {
+            Ok(sources) => sources,
+            Err(rejection) => return rejection,
+        }
+    } else {
+        let Some(format) = Format::of_content_type(content_type) else {
+            return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+        };
+        let Ok(body) = String::from_request(request, &()).await else {
+            return invalid();
+        };
+        match format.parse(&body) {
+            Some(source) => vec![source],
+            None => return invalid(),
+        }
     };
 
-    if orders.is_empty() {
-        return StatusCode::NO_CONTENT.into_response();
+    let Ok(workspace) = sources
+        .iter()
+        .filter_map(|source| {
+            let workspace = source.manifest.workspace.clone()?;
+            Some((workspace, source.workspace_positions.clone()))
+        })
+        .at_most_one()
+    else {
+        return invalid();
+    };
+    // A package that's its own workspace root is listed as one, unless the
+    // workspace has orders of its own
+    let mut grouped = sources.len() > 1;
+    let mut groups = Vec::new();
+
+    let workspace_keywords = workspace
+        .as_ref()
+        .and_then(|(workspace, _)| workspace.package.as_ref())
+        .and_then(|package| package.keywords.clone());
+    if let Some((workspace, positions)) = workspace {
+        let group = Group::new("workspace".to_string(), workspace.metadata, positions);
+        if !group.orders.is_empty() && !has_magic_keyword(workspace_keywords.as_deref()) {
+            return (StatusCode::BAD_REQUEST, "Magic keyword not provided").into_response();
+        }
+        grouped |= group.valid().next().is_some();
+        groups.push(group);
+    }
+
+    for source in sources {
+        let Some(package) = source.manifest.package else {
+            continue;
+        };
+        let keywords = match package.keywords {
+            Some(MaybeInherited::Local(keywords)) => Some(keywords),
+            Some(MaybeInherited::Inherited { .. }) => workspace_keywords.clone(),
+            None => None,
+        };
+        if !has_magic_keyword(keywords.as_deref()) {
+            return (StatusCode::BAD_REQUEST, "Magic keyword not provided").into_response();
+        }
+        groups.push(Group::new(
+            package.name,
+            package.metadata,
+            source.package_positions,
+        ));
     }
 
-    orders
-        .into_iter()
-        .map(|order| format!("{}: {}", order.item, order.quantity))
-        .join("\n")
-        .into_response()
+    if strict {
+        return Json(groups.iter().flat_map(Group::report).collect::<Vec<_>>()).into_response();
+    }
+
+    groups.retain(|group| group.valid().next().is_some());
+    match &groups[..] {
+        [] => StatusCode::NO_CONTENT.into_response(),
+        [group] if !grouped => group.list().into_response(),
+        _ => groups
+            .iter()
+            .map(|group| format!("[{}]\n{}", group.name, group.list()))
+            .join("\n\n")
+            .into_response(),
+    }
 }
 
 #[cfg(test)]
@@ -280,4 +634,236 @@ package:
 
         assert_eq!(body, "Toy train: 5");
     }
+
+    async fn upload(parts: &[(&str, &str)]) -> (StatusCode, String) {
+        let body = parts
+            .iter()
+            .map(|(file_name, manifest)| {
+                format!(
+                    "--gifts\r\nContent-Disposition: form-data; name=\"manifest\"; filename=\"{file_name}\"\r\n\r\n{manifest}\r\n"
+                )
+            })
+            .collect::<String>()
+            + "--gifts--\r\n";
+        let response = router()
+            .oneshot(
+                Request::builder()
+                    .uri("/5/manifest")
+                    .method("POST")
+                    .header("Content-Type", "multipart/form-data; boundary=gifts")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        (status, collect_body(response).await)
+    }
+
+    const WORKSPACE: &str = r#"
+[workspace]
+members = ["sleigh", "stockings"]
+
+[workspace.package]
+keywords = ["Christmas 2024"]
+
+[[workspace.metadata.orders]]
+item = "Reindeer food"
+quantity = 9
+"#;
+
+    #[tokio::test]
+    async fn workspace_root() {
+        let (status, body) = upload(&[("Cargo.toml", WORKSPACE)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "[workspace]\nReindeer food: 9");
+
+        let root = r#"
+[package]
+name = "north-pole"
+keywords = ["Christmas 2024"]
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+
+[workspace]
+members = ["sleigh"]
+"#;
+        let (status, body) = upload(&[("Cargo.toml", root)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "Toy car: 2");
+    }
+
+    #[tokio::test]
+    async fn workspace_members() {
+        let sleigh = r#"
+[package]
+name = "sleigh"
+keywords.workspace = true
+
+[[package.metadata.orders]]
+item = "Bell"
+quantity = 12
+"#;
+        let stockings = r#"
+package:
+  name: stockings
+  keywords:
+    - "Christmas 2024"
+  metadata:
+    orders:
+      - item: "Orange"
+        quantity: 3
+      - item: "Coal"
+        quantity: "none"
+"#;
+
+        let (status, body) = upload(&[
+            ("Cargo.toml", WORKSPACE),
+            ("sleigh/Cargo.toml", sleigh),
+            ("stockings/Cargo.yaml", stockings),
+        ])
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            "[workspace]\nReindeer food: 9\n\n[sleigh]\nBell: 12\n\n[stockings]\nOrange: 3"
+        );
+
+        let (status, body) = upload(&[("sleigh/Cargo.toml", sleigh)]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "Magic keyword not provided");
+
+        let (status, body) = upload(&[("Cargo.toml", WORKSPACE), ("Cargo.toml", WORKSPACE)]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "Invalid manifest");
+
+        let (status, _) = upload(&[("Cargo.lock", "version = 3")]).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn strict_report() {
+        let check = |content_type: &'static str, manifest: &'static str| {
+            router().oneshot(
+                Request::builder()
+                    .uri("/5/manifest?strict=true")
+                    .method("POST")
+                    .header("Content-Type", content_type)
+                    .body(manifest.to_string())
+                    .unwrap(),
+            )
+        };
+        let json = |body: String| serde_json::from_str::<serde_json::Value>(&body).unwrap();
+
+        let response = check(
+            "application/toml",
+            r#"[package]
+name = "typos"
+keywords = ["Christmas 2024"]
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+
+[[package.metadata.orders]]
+item = "Coal"
+quantity = "lots"
+
+[[package.metadata.orders]]
+item = "Lego brick"
+quanity = 230
+"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json(collect_body(response).await),
+            serde_json::json!([
+                {
+                    "package": "typos",
+                    "index": 0,
+                    "order": { "item": "Toy car", "quantity": 2 },
+                    "line": 5,
+                    "column": 1,
+                },
+                {
+                    "package": "typos",
+                    "index": 1,
+                    "error": "invalid type: string \"lots\", expected u32",
+                    "line": 9,
+                    "column": 1,
+                },
+                {
+                    "package": "typos",
+                    "index": 2,
+                    "error": "missing field `quantity`",
+                    "line": 13,
+                    "column": 1,
+                },
+            ])
+        );
+
+        let response = check(
+            "application/toml",
+            r#"package = { name = "inline", keywords = ["Christmas 2024"], metadata.orders = [{ item = "Debt", quantity = -1 }] }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            json(collect_body(response).await),
+            serde_json::json!([{
+                "package": "inline",
+                "index": 0,
+                "error": "invalid value: integer `-1`, expected u32",
+                "line": 1,
+                "column": 80,
+            }])
+        );
+
+        let response = check(
+            "application/yaml",
+            r#"
+package:
+  name: no-positions
+  keywords: ["Christmas 2024"]
+  metadata:
+    orders:
+      - item: "Toy train"
+"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            json(collect_body(response).await),
+            serde_json::json!([{
+                "package": "no-positions",
+                "index": 0,
+                "error": "missing field `quantity`",
+            }])
+        );
+
+        let response = check(
+            "application/toml",
+            r#"[package]
+name = "scribbles"
+keywords = ["Christmas 2024"]
+metadata.orders = "x"
+"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            json(collect_body(response).await),
+            serde_json::json!([{
+                "package": "scribbles",
+                "error": "invalid type: string \"x\", expected a sequence",
+                "line": 4,
+                "column": 19,
+            }])
+        );
+    }
 }
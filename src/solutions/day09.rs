@@ -1,67 +1,1437 @@
 use std::{sync::Arc, time::Duration};
 
 use axum::{
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    Extension,
+    extract::{FromRequestParts, Query},
+    http::{header::HeaderName, request::Parts, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension, Json,
 };
-use leaky_bucket::RateLimiter;
 use serde_json::json;
-use tokio::sync::RwLock;
+use sqlx::PgPool;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        RwLock,
+    },
+    time::Instant,
+};
+
+use crate::rate_limit::{Bucket, BucketConfig, Client, KeyedBuckets, Quota};
+
+mod ledger;
+mod units;
+
+use units::{Conversion, Measure, Unit};
+
+type BucketsLock = Extension<Arc<RwLock<Buckets>>>;
+
+/// Changes to a [`BucketConfig`]. Settings left out keep their value.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketSettings {
+    initial: Option<usize>,
+    max: Option<usize>,
+    interval_ms: Option<u64>,
+    refill_amount: Option<usize>,
+    /// Milk added to the stock, when a ledger is kept.
+    restock: Option<Measure>,
+}
+
+impl BucketSettings {
+    /// `config`, with these settings applied.
+    fn apply(&self, config: BucketConfig) -> Result<BucketConfig, &'static str> {
+        BucketConfig {
+            initial: self.initial.unwrap_or(config.initial),
+            max: self.max.unwrap_or(config.max),
+            interval_ms: self.interval_ms.unwrap_or(config.interval_ms),
+            refill_amount: self.refill_amount.unwrap_or(config.refill_amount),
+        }
+        .validate()
+    }
+}
+
+/// A milk bucket for each client, created on their first withdrawal, and
+/// optionally a cap on withdrawals across all clients, so new clients can't
+/// each bring a fresh bucket.
+///
+/// Buckets built with [`Buckets::restore`] also keep a ledger of withdrawals
+/// and refills in Postgres, and only pour milk that's in stock.
+pub struct Buckets {
+    clients: KeyedBuckets<Client>,
+    cap: Option<Arc<Bucket>>,
+    ledger: Option<Ledger>,
+    changes: broadcast::Sender<Change>,
+}
+
+/// Something that changed how much milk clients can take.
+#[derive(Debug, Clone, PartialEq)]
+enum Change {
+    Withdrawal(Client),
+    Refill,
+}
+
+struct Ledger {
+    pool: PgPool,
+    stock_litres: f64,
+}
+
+/// Milk set aside from the stock for a withdrawal, until it's recorded in
+/// the ledger or put back.
+struct Reservation {
+    pool: PgPool,
+    poured: Vec<Measure>,
+    litres: f64,
+}
+
+impl Reservation {
+    /// Records the withdrawal by `client`, in one transaction.
+    async fn record(&self, client: &Client) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        for &measure in &self.poured {
+            ledger::record(
+                &mut *transaction,
+                ledger::Kind::Withdrawal,
+                &client.to_string(),
+                measure,
+            )
+            .await?;
+        }
+        transaction.commit().await
+    }
+}
+
+/// What a withdrawal without a measure takes from the stock.
+const CARTON: Measure = Measure {
+    value: 1.0,
+    unit: Unit::Litres,
+};
+
+impl Buckets {
+    /// The cap `MILK_CAP` changes: 20 withdrawals a second, with bursts of
+    /// up to 100.
+    pub const CAP: BucketConfig = BucketConfig {
+        initial: 100,
+        max: 100,
+        interval_ms: 50,
+        refill_amount: 1,
+    };
+
+    /// How many changes a status stream can fall behind by before it
+    /// misses some.
+    const CHANGES: usize = 64;
+
+    pub fn new(config: BucketConfig) -> Self {
+        Self {
+            clients: KeyedBuckets::new(config),
+            cap: None,
+            ledger: None,
+            changes: broadcast::Sender::new(Self::CHANGES),
+        }
+    }
+
+    /// Picks up the stock recorded in `pool`'s ledger, and records every
+    /// withdrawal and refill from here on in it.
+    pub async fn restore(pool: PgPool, config: BucketConfig) -> Result<Self, sqlx::Error> {
+        let totals = ledger::totals(&pool).await?;
+        Ok(Self {
+            ledger: Some(Ledger {
+                pool,
+                stock_litres: totals.stock_litres(),
+            }),
+            ..Self::new(config)
+        })
+    }
+
+    /// Limits withdrawals across all clients to `cap`'s rate, or lifts the
+    /// limit.
+    pub fn with_cap(self, cap: Option<BucketConfig>) -> Self {
+        Self {
+            cap: cap.map(|cap| Arc::new(Bucket::new(cap))),
+            ..self
+        }
+    }
+
+    /// The cap asked for by `MILK_CAP`, if it's set: JSON [`BucketSettings`]
+    /// changing [`Buckets::CAP`], so `{}` asks for that cap as it is.
+    pub fn cap_from_env() -> Result<Option<BucketConfig>, &'static str> {
+        let Ok(settings) = std::env::var("MILK_CAP") else {
+            return Ok(None);
+        };
+        let settings = serde_json::from_str::<BucketSettings>(&settings)
+            .map_err(|_| "Invalid bucket settings\n")?;
+        if settings.restock.is_some() {
+            return Err("The cap has no stock to restock\n");
+        }
+        settings.apply(Self::CAP).map(Some)
+    }
+
+    /// The buckets a withdrawal by `client` is taken from: their own, and the
+    /// cap if there is one.
+    fn buckets_for(&mut self, client: &Client) -> (Arc<Bucket>, Option<Arc<Bucket>>) {
+        (self.clients.bucket(client), self.cap.clone())
+    }
+
+    /// What `client` may still take, counting the cap.
+    fn quota(&self, client: &Client) -> Quota {
+        let quota = self.clients.quota(client);
+        match &self.cap {
+            Some(cap) => quota.min(cap.quota()),
+            None => quota,
+        }
+    }
+
+    /// Sets everything `poured` aside from the stock, if a ledger is kept.
+    /// Either all of it is set aside or none.
+    fn reserve(
+        &mut self,
+        poured: Vec<Measure>,
+    ) -> Result<Option<Reservation>, (StatusCode, &'static str)> {
+        let Some(ledger) = &mut self.ledger else {
+            return Ok(None);
+        };
+        if poured.iter().any(|measure| measure.value <= 0.0) {
+            return Err((StatusCode::BAD_REQUEST, "Only milk can be withdrawn\n"));
+        }
+        let litres = poured.iter().map(Measure::litres).sum::<f64>();
+        if litres > ledger.stock_litres {
+            return Err((StatusCode::TOO_MANY_REQUESTS, "Not enough milk in stock\n"));
+        }
+
+        ledger.stock_litres -= litres;
+        Ok(Some(Reservation {
+            pool: ledger.pool.clone(),
+            poured,
+            litres,
+        }))
+    }
+
+    /// Puts milk set aside for a withdrawal that didn't happen back in stock.
+    fn release(&mut self, reservation: Reservation) {
+        if let Some(ledger) = &mut self.ledger {
+            ledger.stock_litres += reservation.litres;
+        }
+    }
+
+    /// Fills every bucket back up, applying `settings` to the clients' and
+    /// restocking if a ledger is kept.
+    async fn refill(
+        &mut self,
+        client: &Client,
+        settings: BucketSettings,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        let restock = settings.restock;
+        let config = settings
+            .apply(self.clients.config())
+            .map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+
+        match (&mut self.ledger, restock) {
+            (None, None) => (),
+            (None, Some(_)) => {
+                return Err((StatusCode::BAD_REQUEST, "No stock is kept to restock\n"));
+            }
+            (Some(_), Some(restock)) if restock.value < 0.0 => {
+                return Err((StatusCode::BAD_REQUEST, "Restocking can't remove milk\n"));
+            }
+            (Some(ledger), restock) => {
+                let restock = restock.unwrap_or(Measure {
+                    value: 0.0,
+                    ..CARTON
+                });
+                ledger::record(
+                    &ledger.pool,
+                    ledger::Kind::Refill,
+                    &client.to_string(),
+                    restock,
+                )
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Could not record refill\n",
+                    )
+                })?;
+                ledger.stock_litres += restock.litres();
+            }
+        }
+
+        self.reset(config);
+        self.announce(Change::Refill);
+        Ok(())
+    }
+
+    /// Tells status streams about `change`.
+    fn announce(&self, change: Change) {
+        // Fails only when nobody is listening
+        let _ = self.changes.send(change);
+    }
+
+    /// `subject`'s milk as it stands, without making a bucket for a client
+    /// that has none. There's none for the cap when withdrawals aren't
+    /// capped.
+    fn status(&self, subject: &Subject) -> Option<Status> {
+        let name = subject.to_string();
+        let status = match subject {
+            Subject::Client(client) => match self.clients.get(client) {
+                Some(bucket) => Status::of(name, [&*bucket]),
+                None => {
+                    let config = self.clients.config();
+                    Status {
+                        client: name,
+                        tokens: config.initial,
+                        capacity: config.max,
+                        next_refill_ms: None,
+                        clients: None,
+                    }
+                }
+            },
+            Subject::Cap => Status::of(name, [&**self.cap.as_ref()?]),
+            Subject::All => Status {
+                clients: Some(self.clients.buckets().count()),
+                ..Status::of(name, self.clients.buckets().map(|bucket| &**bucket))
+            },
+        };
+        Some(status)
+    }
+
+    /// Where the ledger is kept, if it is.
+    fn ledger_pool(&self) -> Option<PgPool> {
+        self.ledger.as_ref().map(|ledger| ledger.pool.clone())
+    }
+
+    fn reset(&mut self, config: BucketConfig) {
+        self.clients.reset(config);
+        if let Some(cap) = &mut self.cap {
+            *cap = Arc::new(Bucket::new(cap.config()));
+        }
+    }
+}
+
+/// How long a client is willing to wait for milk rather than be turned
+/// away: `?wait=` in seconds, or with an `ms` or `s` suffix, or else a
+/// `Prefer: wait=<seconds>` header. Waits are cut short at [`Wait::MAX`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wait(Option<Duration>);
+
+/// Takes `amount` from both a client's `bucket` and the `cap`, if there is
+/// one, or from neither. Withdrawals take from these buckets under the
+/// [`Buckets`] lock, so neither can run dry between checking and taking.
+fn try_acquire(bucket: &Bucket, cap: Option<&Bucket>, amount: usize) -> bool {
+    bucket.remaining() >= amount
+        && cap.is_none_or(|cap| cap.remaining() >= amount)
+        && bucket.try_acquire(amount)
+        && cap.is_none_or(|cap| cap.try_acquire(amount))
+}
+
+#[derive(serde::Deserialize)]
+struct WaitQuery {
+    wait: Option<String>,
+}
+
+const PREFER: HeaderName = HeaderName::from_static("prefer");
+
+impl Wait {
+    pub const MAX: Duration = Duration::from_secs(30);
+
+    fn parse(text: &str) -> Option<Duration> {
+        let text = text.trim();
+        let (number, scale) = match text.strip_suffix("ms") {
+            Some(millis) => (millis, 0.001),
+            None => (text.strip_suffix('s').unwrap_or(text), 1.0),
+        };
+        let seconds = number.trim().parse::<f64>().ok()? * scale;
+        Duration::try_from_secs_f64(seconds).ok()
+    }
+
+    /// Takes `amount` from both `client`'s bucket and the cap, waiting for
+    /// them to hold enough if the client is willing to. A wait that runs out
+    /// takes nothing from either.
+    ///
+    /// The buckets are looked up again after every sleep, since a refill
+    /// replaces them, and a wait keeps trying up to its deadline even when
+    /// the next refill comes later, in case a refill comes first.
+    async fn acquire(self, buckets: &RwLock<Buckets>, client: &Client, amount: usize) -> bool {
+        let deadline = self
+            .0
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            let wait = {
+                let mut buckets = buckets.write().await;
+                let (bucket, cap) = buckets.buckets_for(client);
+                if try_acquire(&bucket, cap.as_deref(), amount) {
+                    return true;
+                }
+                let cap_wait = cap.map_or(Duration::ZERO, |cap| cap.time_until(amount));
+                bucket.time_until(amount).max(cap_wait)
+            };
+            let left = deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+                .unwrap_or_default();
+            if left.is_zero() {
+                return false;
+            }
+            tokio::time::sleep(wait.min(left)).await;
+        }
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Wait {
+    type Rejection = (StatusCode, &'static str);
 
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let invalid = (StatusCode::BAD_REQUEST, "Invalid wait\n");
+        let Ok(Query(WaitQuery { wait })) = Query::try_from_uri(&parts.uri) else {
+            return Err(invalid);
+        };
+        let preferred = parts
+            .headers
+            .get_all(PREFER)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .find_map(|preference| preference.trim().strip_prefix("wait="))
+            .map(str::to_string);
+
+        match wait.or(preferred) {
+            None => Ok(Wait(None)),
+            Some(wait) => match Self::parse(&wait) {
+                Some(wait) => Ok(Wait(Some(wait.min(Self::MAX)))),
+                None => Err(invalid),
+            },
+        }
+    }
+}
+
+/// Milk to convert: either a [`Conversion`] between any two units, or the
+/// original single-key form like `{"gallons": 2}`, which converts between a
+/// fixed pair of units.
 #[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[serde(untagged)]
 pub enum MilkMeasure {
-    Gallons(f32),
-    Liters(f32),
-    Litres(f32),
-    Pints(f32),
+    Conversion(Conversion),
+    Legacy(LegacyMeasure),
 }
 
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LegacyMeasure {
+    Gallons(f64),
+    Liters(f64),
+    Litres(f64),
+    Pints(f64),
+}
+
+impl MilkMeasure {
+    /// The milk being converted, which is what gets withdrawn.
+    fn poured(&self) -> Measure {
+        let (value, unit) = match *self {
+            MilkMeasure::Conversion(conversion) => (conversion.value, conversion.from),
+            MilkMeasure::Legacy(LegacyMeasure::Gallons(value)) => (value, Unit::UsGallons),
+            MilkMeasure::Legacy(LegacyMeasure::Liters(value)) => (value, Unit::Litres),
+            MilkMeasure::Legacy(LegacyMeasure::Litres(value)) => (value, Unit::Litres),
+            MilkMeasure::Legacy(LegacyMeasure::Pints(value)) => (value, Unit::ImperialPints),
+        };
+        Measure { value, unit }
+    }
+
+    /// The converted measure, keyed by its unit. Legacy measures keep the
+    /// unit spellings they have always been answered with.
+    fn convert(&self) -> serde_json::Value {
+        let (conversion, unit) = match *self {
+            MilkMeasure::Conversion(conversion) => (conversion, conversion.to.name()),
+            MilkMeasure::Legacy(LegacyMeasure::Gallons(value)) => (
+                Conversion {
+                    from: Unit::UsGallons,
+                    to: Unit::Litres,
+                    value,
+                },
+                "liters",
+            ),
+            MilkMeasure::Legacy(LegacyMeasure::Liters(value)) => (
+                Conversion {
+                    from: Unit::Litres,
+                    to: Unit::UsGallons,
+                    value,
+                },
+                "gallons",
+            ),
+            MilkMeasure::Legacy(LegacyMeasure::Litres(value)) => (
+                Conversion {
+                    from: Unit::Litres,
+                    to: Unit::ImperialPints,
+                    value,
+                },
+                "pints",
+            ),
+            MilkMeasure::Legacy(LegacyMeasure::Pints(value)) => (
+                Conversion {
+                    from: Unit::ImperialPints,
+                    to: Unit::Litres,
+                    value,
+                },
+                "litres",
+            ),
+        };
+
+        json!({ unit: conversion.result() })
+    }
+}
+
+/// What a withdrawal pours: a carton, or milk to convert.
+enum Order {
+    Carton,
+    Convert(MilkMeasure),
+    /// Several measures, converted together for a token each.
+    Batch(Vec<MilkMeasure>),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Measures {
+    Batch(Vec<MilkMeasure>),
+    Single(MilkMeasure),
+}
+
+impl Order {
+    /// The order in a request. Only JSON bodies are read; without one, a
+    /// carton is withdrawn. A batch must have something in it.
+    fn parse(headers: &HeaderMap, body: Option<&str>) -> Option<Self> {
+        let Some("application/json") = headers
+            .get("content-type")
+            .and_then(|content| content.to_str().ok())
+        else {
+            return Some(Order::Carton);
+        };
+
+        match serde_json::from_str(body?).ok()? {
+            Measures::Single(measurement) => Some(Order::Convert(measurement)),
+            Measures::Batch(measurements) if measurements.is_empty() => None,
+            Measures::Batch(measurements) => Some(Order::Batch(measurements)),
+        }
+    }
+
+    fn tokens(&self) -> usize {
+        match self {
+            Order::Carton | Order::Convert(_) => 1,
+            Order::Batch(measurements) => measurements.len(),
+        }
+    }
+
+    fn poured(&self) -> Vec<Measure> {
+        match self {
+            Order::Carton => vec![CARTON],
+            Order::Convert(measurement) => vec![measurement.poured()],
+            Order::Batch(measurements) => measurements.iter().map(MilkMeasure::poured).collect(),
+        }
+    }
+
+    fn response(&self) -> Response {
+        match self {
+            Order::Carton => (StatusCode::OK, "Milk withdrawn\n").into_response(),
+            Order::Convert(measurement) => measurement.convert().to_string().into_response(),
+            Order::Batch(measurements) => {
+                serde_json::Value::from_iter(measurements.iter().map(MilkMeasure::convert))
+                    .to_string()
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Withdraws milk, optionally converting a measure of it, or a JSON array of
+/// measures for a token each. A batch is poured whole or not at all. Every
+/// response tells the client how much milk they have left and when to come
+/// back.
+///
+/// Unlike other rate limited routes, this one doesn't sit behind a
+/// [`RateLimitLayer`](crate::rate_limit::RateLimitLayer): its cost depends on
+/// the body, it can wait for tokens, and it has to draw on the cap and set
+/// stock aside along with them, all of which a layer can't see.
 pub async fn milk(
-    Extension(milk_bucket): Extension<Arc<RwLock<RateLimiter>>>,
+    Extension(buckets): BucketsLock,
+    client: Client,
+    wait: Wait,
     headers: HeaderMap,
     body: Option<String>,
+) -> Response {
+    let Some(order) = Order::parse(&headers, body.as_deref()) else {
+        let quota = buckets.read().await.quota(&client);
+        return (quota, StatusCode::BAD_REQUEST).into_response();
+    };
+    let tokens = order.tokens();
+
+    // Milk is set aside before any tokens are taken, so a withdrawal turned
+    // away for want of stock costs nothing. Waiting and recording happen
+    // outside the lock, so other clients aren't held up.
+    let reservation = {
+        let mut buckets = buckets.write().await;
+        let (bucket, cap) = buckets.buckets_for(&client);
+        let cap_max = cap.map_or(usize::MAX, |cap| cap.config().max);
+        if tokens > bucket.config().max.min(cap_max) {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                buckets.quota(&client),
+                "Batch is bigger than the bucket\n",
+            )
+                .into_response();
+        }
+        match buckets.reserve(order.poured()) {
+            Ok(reservation) => reservation,
+            Err(rejection) => return (buckets.quota(&client), rejection).into_response(),
+        }
+    };
+
+    if !wait.acquire(&buckets, &client, tokens).await {
+        let mut buckets = buckets.write().await;
+        if let Some(reservation) = reservation {
+            buckets.release(reservation);
+        }
+        let quota = buckets.quota(&client);
+        return (StatusCode::TOO_MANY_REQUESTS, quota, "No milk available\n").into_response();
+    }
+    if let Some(reservation) = reservation {
+        if reservation.record(&client).await.is_err() {
+            let mut buckets = buckets.write().await;
+            buckets.release(reservation);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                buckets.quota(&client),
+                "Could not record withdrawal\n",
+            )
+                .into_response();
+        }
+    }
+
+    let buckets = buckets.read().await;
+    buckets.announce(Change::Withdrawal(client.clone()));
+    (buckets.quota(&client), order.response()).into_response()
+}
+
+/// Fills every bucket back up. A JSON body of [`BucketSettings`] also
+/// changes how clients' buckets are sized and refilled from now on.
+pub async fn refill(
+    Extension(buckets): BucketsLock,
+    client: Client,
+    body: String,
 ) -> impl IntoResponse {
-    let bucket = milk_bucket.read().await;
-    let aquired = bucket.try_acquire(1);
-    drop(bucket);
+    let settings = if body.trim().is_empty() {
+        BucketSettings::default()
+    } else {
+        match serde_json::from_str::<BucketSettings>(&body) {
+            Ok(settings) => settings,
+            Err(_) => {
+                return (StatusCode::BAD_REQUEST, "Invalid bucket settings\n").into_response()
+            }
+        }
+    };
 
-    if let Some("application/json") = headers
-        .get("content-type")
-        .and_then(|content| content.to_str().ok())
-    {
-        let Ok(measurement) = serde_json::from_str::<MilkMeasure>(&body.unwrap()) else {
-            return StatusCode::BAD_REQUEST.into_response();
+    match buckets.write().await.refill(&client, settings).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+pub async fn config(Extension(buckets): BucketsLock) -> impl IntoResponse {
+    Json(buckets.read().await.clients.config())
+}
+
+/// How much milk a client can take, and how long until their bucket next
+/// gains some. Buckets that are full, or not made yet, aren't refilling.
+///
+/// The status of the cap, or of every client together, is told the same
+/// way, with `client` naming which it is. Together, clients have the milk
+/// and room of all their buckets, and refill when the first of them does.
+#[derive(Debug, serde::Serialize)]
+struct Status {
+    client: String,
+    tokens: usize,
+    capacity: usize,
+    next_refill_ms: Option<u64>,
+    /// How many clients have buckets, for the status of all of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clients: Option<usize>,
+}
+
+impl Status {
+    /// The status of `buckets` together, told as `client`'s.
+    fn of<'a>(client: String, buckets: impl IntoIterator<Item = &'a Bucket>) -> Self {
+        let mut status = Status {
+            client,
+            tokens: 0,
+            capacity: 0,
+            next_refill_ms: None,
+            clients: None,
         };
+        for bucket in buckets {
+            let tokens = bucket.remaining();
+            let capacity = bucket.config().max;
+            status.tokens += tokens;
+            status.capacity += capacity;
+            if tokens < capacity {
+                let next_refill =
+                    u64::try_from(bucket.next_refill().as_millis()).unwrap_or(u64::MAX);
+                status.next_refill_ms = Some(
+                    status
+                        .next_refill_ms
+                        .map_or(next_refill, |soonest| soonest.min(next_refill)),
+                );
+            }
+        }
+        status
+    }
+}
+
+/// Whose milk a status is about: the calling client's unless `?of=` names
+/// another client, or `cap`, or `all` for every client together.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject {
+    Client(Client),
+    Cap,
+    All,
+}
 
-        if !aquired {
-            return (StatusCode::TOO_MANY_REQUESTS, "No milk available\n").into_response();
+impl std::fmt::Display for Subject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Subject::Client(client) => client.fmt(f),
+            Subject::Cap => write!(f, "cap"),
+            Subject::All => write!(f, "all"),
         }
+    }
+}
 
-        return match measurement {
-            MilkMeasure::Gallons(g) => json!({"liters": g * 3.78541}),
-            MilkMeasure::Liters(l) => json!({"gallons": l * 0.26417}),
-            MilkMeasure::Litres(l) => json!({"pints": l * 1.759754}),
-            MilkMeasure::Pints(p) => json!({"litres": p * 0.568261291}),
+impl Subject {
+    /// Whether a withdrawal by `client` took some of this subject's milk.
+    fn withdrawn_by(&self, client: &Client) -> bool {
+        match self {
+            Subject::Client(subject) => subject == client,
+            Subject::Cap | Subject::All => true,
         }
-        .to_string()
-        .into_response();
     }
+}
 
-    if aquired {
-        (StatusCode::OK, "Milk withdrawn\n")
-    } else {
-        (StatusCode::TOO_MANY_REQUESTS, "No milk available\n")
+#[derive(serde::Deserialize)]
+struct StatusQuery {
+    of: Option<String>,
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Subject {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(StatusQuery { of }) = Query::<StatusQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        match of.as_deref() {
+            None => Ok(Subject::Client(Client::of(
+                &parts.headers,
+                &parts.extensions,
+            ))),
+            Some("cap") => Ok(Subject::Cap),
+            Some("all") => Ok(Subject::All),
+            Some(client) => client
+                .parse()
+                .map(Subject::Client)
+                .map_err(|_| (StatusCode::BAD_REQUEST, "Unknown client\n").into_response()),
+        }
+    }
+}
+
+fn uncapped() -> Response {
+    (StatusCode::NOT_FOUND, "Withdrawals aren't capped\n").into_response()
+}
+
+pub async fn status(Extension(buckets): BucketsLock, subject: Subject) -> Response {
+    match buckets.read().await.status(&subject) {
+        Some(status) => Json(status).into_response(),
+        None => uncapped(),
     }
-    .into_response()
 }
 
-pub async fn refill(Extension(milk_bucket): Extension<Arc<RwLock<RateLimiter>>>) {
-    let mut bucket = milk_bucket.write().await;
-    *bucket = RateLimiter::builder()
-        .initial(5)
-        .max(5)
-        .interval(Duration::from_millis(1_000))
-        .build();
+/// Server-sent events carrying a [`Status`], first as it stands and then
+/// again after each withdrawal from it, each refill, and each time it gains
+/// milk. Events are named after what happened; a stream that falls behind
+/// skips ahead with a fresh `status`.
+pub async fn status_stream(Extension(buckets): BucketsLock, subject: Subject) -> Response {
+    let changes = {
+        let buckets = buckets.read().await;
+        if buckets.status(&subject).is_none() {
+            return uncapped();
+        }
+        buckets.changes.subscribe()
+    };
+    let events = futures_util::stream::unfold(
+        (buckets, subject, changes, true),
+        move |(buckets, subject, mut changes, first)| async move {
+            let event = if first {
+                "status"
+            } else {
+                next_change(&buckets, &subject, &mut changes).await?
+            };
+            let status = buckets.read().await.status(&subject)?;
+
+            Some((
+                Event::default().event(event).json_data(status),
+                (buckets, subject, changes, false),
+            ))
+        },
+    );
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Waits for the next change to `subject`'s milk, and names it.
+async fn next_change(
+    buckets: &RwLock<Buckets>,
+    subject: &Subject,
+    changes: &mut broadcast::Receiver<Change>,
+) -> Option<&'static str> {
+    loop {
+        let next_refill = buckets.read().await.status(subject)?.next_refill_ms;
+        let replenished = async {
+            match next_refill {
+                Some(millis) => tokio::time::sleep(Duration::from_millis(millis)).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(Change::Withdrawal(by)) if subject.withdrawn_by(&by) => {
+                    return Some("withdrawal");
+                }
+                Ok(Change::Withdrawal(_)) => (),
+                Ok(Change::Refill) => return Some("refill"),
+                Err(RecvError::Lagged(_)) => return Some("status"),
+                Err(RecvError::Closed) => return None,
+            },
+            () = replenished => return Some("replenishment"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct LedgerPage {
+    limit: Option<i64>,
+}
+
+impl LedgerPage {
+    const DEFAULT_LIMIT: i64 = 100;
+    const MAX_LIMIT: i64 = 1_000;
+}
+
+/// The latest withdrawals and refills, newest first.
+pub async fn ledger(
+    Extension(buckets): BucketsLock,
+    Query(LedgerPage { limit }): Query<LedgerPage>,
+) -> impl IntoResponse {
+    let Some(pool) = buckets.read().await.ledger_pool() else {
+        return (StatusCode::NOT_FOUND, "No ledger is kept\n").into_response();
+    };
+    let limit = limit
+        .unwrap_or(LedgerPage::DEFAULT_LIMIT)
+        .clamp(1, LedgerPage::MAX_LIMIT);
+
+    match ledger::entries(&pool, limit).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// The stock, along with how much milk has been refilled and withdrawn.
+pub async fn inventory(Extension(buckets): BucketsLock) -> impl IntoResponse {
+    let Some(pool) = buckets.read().await.ledger_pool() else {
+        return (StatusCode::NOT_FOUND, "No ledger is kept\n").into_response();
+    };
+
+    match ledger::totals(&pool).await {
+        Ok(totals) => Json(json!({
+            "stock_litres": totals.stock_litres(),
+            "refills": totals.refills,
+            "refilled_litres": totals.refilled_litres,
+            "withdrawals": totals.withdrawals,
+            "withdrawn_litres": totals.withdrawn_litres,
+        }))
+        .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{try_acquire, BucketConfig, Buckets, Client, Ledger, Subject, Wait};
+    use crate::{
+        rate_limit::Clients, router, router_with, solutions::day12, test_utils::collect_body,
+        DAY12_IDLE_TIMEOUT,
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Extension, Router,
+    };
+    use http_body_util::BodyExt as _;
+    use tower::ServiceExt;
+
+    /// The app, with the API keys used here handed out.
+    fn app() -> Router {
+        let keys = [
+            "noisy",
+            "quiet",
+            "polite",
+            "batch",
+            "load",
+            "converter",
+            "reporting",
+            "someone else",
+        ];
+        router().layer(Extension(Arc::new(Clients::new(keys))))
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        body: &'static str,
+    ) -> (StatusCode, String) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .method(method)
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        (status, collect_body(response).await)
+    }
+
+    async fn withdraw(app: &Router, header: (&str, &str)) -> StatusCode {
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/9/milk")
+                    .method("POST")
+                    .header(header.0, header.1)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+    }
+
+    async fn next_event(body: &mut Body) -> (String, serde_json::Value) {
+        let frame = body.frame().await.unwrap().unwrap();
+        let event = String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap();
+        let field = |name: &str| {
+            event
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap()
+                .to_string()
+        };
+        (
+            field("event: "),
+            serde_json::from_str(&field("data: ")).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn per_client_buckets() {
+        let app = app();
+        for _ in 0..5 {
+            assert_eq!(withdraw(&app, ("X-Api-Key", "noisy")).await, StatusCode::OK);
+        }
+        assert_eq!(
+            withdraw(&app, ("X-Api-Key", "noisy")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        assert_eq!(withdraw(&app, ("X-Api-Key", "quiet")).await, StatusCode::OK);
+        assert_eq!(
+            withdraw(&app, ("X-Forwarded-For", "203.0.113.7, 10.0.0.1")).await,
+            StatusCode::OK
+        );
+
+        // Unknown keys don't get buckets of their own
+        for _ in 0..5 {
+            assert_eq!(
+                withdraw(&app, ("X-Api-Key", "made up")).await,
+                StatusCode::OK
+            );
+        }
+        assert_eq!(
+            withdraw(&app, ("X-Api-Key", "made up too")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limit_headers() {
+        let app = app();
+        let request = || {
+            Request::builder()
+                .uri("/9/milk")
+                .method("POST")
+                .header("X-Api-Key", "polite")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let header = |response: &axum::response::Response, name: &str| {
+            response.headers()[name].to_str().unwrap().to_string()
+        };
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "RateLimit-Limit"), "5");
+        assert_eq!(header(&response, "RateLimit-Remaining"), "4");
+        assert_eq!(header(&response, "RateLimit-Reset"), "1");
+        assert_eq!(header(&response, "Retry-After"), "0");
+
+        for _ in 0..4 {
+            app.clone().oneshot(request()).await.unwrap();
+        }
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, "RateLimit-Remaining"), "0");
+        assert_eq!(header(&response, "RateLimit-Reset"), "5");
+        assert_eq!(header(&response, "Retry-After"), "1");
+    }
+
+    #[tokio::test]
+    async fn waiting_withdrawals() {
+        let app = app();
+        send(
+            &app,
+            "POST",
+            "/9/refill",
+            r#"{"initial": 1, "max": 1, "interval_ms": 200}"#,
+        )
+        .await;
+        let withdraw = |uri: &str, prefer: Option<&str>| {
+            let mut request = Request::builder()
+                .uri(uri)
+                .method("POST")
+                .header("X-Api-Key", "batch");
+            if let Some(prefer) = prefer {
+                request = request.header("Prefer", prefer);
+            }
+            let response = app.clone().oneshot(request.body(Body::empty()).unwrap());
+            async { response.await.unwrap().status() }
+        };
+
+        assert_eq!(withdraw("/9/milk", None).await, StatusCode::OK);
+        assert_eq!(
+            withdraw("/9/milk", None).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(withdraw("/9/milk?wait=1s", None).await, StatusCode::OK);
+        assert_eq!(
+            withdraw("/9/milk", Some("respond-async, wait=0.05")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(withdraw("/9/milk", Some("wait=1")).await, StatusCode::OK);
+        assert_eq!(
+            withdraw("/9/milk?wait=soon", None).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn configure_buckets() {
+        let app = app();
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/9/refill",
+            r#"{"initial": 2, "max": 2, "interval_ms": 500}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&app, "GET", "/9/config", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "initial": 2, "max": 2, "interval_ms": 500, "refill_amount": 1 })
+        );
+
+        for _ in 0..2 {
+            assert_eq!(withdraw(&app, ("X-Api-Key", "load")).await, StatusCode::OK);
+        }
+        assert_eq!(
+            withdraw(&app, ("X-Api-Key", "load")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        let (status, _) = send(&app, "POST", "/9/refill", r#"{"initial": 3}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, "POST", "/9/refill", r#"{"refill_amount": 0}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/9/refill",
+            r#"{"initial": 0, "max": 2000, "interval_ms": 18446744073709551615}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            withdraw(&app, ("X-Api-Key", "load")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn conversions() {
+        let app = app();
+        let convert = |body: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/9/milk")
+                    .method("POST")
+                    .header("Content-Type", "application/json")
+                    .header("X-Api-Key", "converter")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+        let json = |body: String| serde_json::from_str::<serde_json::Value>(&body).unwrap();
+
+        let response = convert(r#"{"from": "cups", "to": "millilitres", "value": 3}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json(collect_body(response).await);
+        assert!((body["millilitres"].as_f64().unwrap() - 709.7647095).abs() < 1e-9);
+
+        let response = convert(r#"{"gallons": 2}"#).await.unwrap();
+        let body = json(collect_body(response).await);
+        assert!((body["liters"].as_f64().unwrap() - 7.570823568).abs() < 1e-9);
+
+        let response = convert(r#"{"from": "cups", "to": "furlongs", "value": 3}"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn batch_conversions() {
+        let app = app();
+        let convert = |body: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/9/milk")
+                    .method("POST")
+                    .header("Content-Type", "application/json")
+                    .header("X-Api-Key", "reporting")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+        let batch = r#"[
+            {"from": "litres", "to": "millilitres", "value": 2},
+            {"gallons": 1},
+            {"from": "imperial_pints", "to": "imperial_fluid_ounces", "value": 1}
+        ]"#;
+
+        let response = convert(batch).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["RateLimit-Remaining"], "2");
+        let body = serde_json::from_str::<serde_json::Value>(&collect_body(response).await);
+        assert_eq!(
+            body.unwrap(),
+            serde_json::json!([
+                { "millilitres": 2000.0 },
+                { "liters": 3.785411784 },
+                { "imperial_fluid_ounces": 20.0 },
+            ])
+        );
+
+        let response = convert(batch).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["RateLimit-Remaining"], "2");
+
+        let response = convert(r#"[{"gallons": 1}, {"furlongs": 1}]"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = convert("[]").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = convert(r#"[{"gallons": 1}, {"gallons": 2}, {"gallons": 3}, {"gallons": 4}, {"gallons": 5}, {"gallons": 6}]"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = convert(r#"[{"gallons": 1}, {"gallons": 2}]"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["RateLimit-Remaining"], "0");
+    }
+
+    #[tokio::test]
+    async fn ledger_stock() {
+        // Nothing listens here, so every withdrawal that gets as far as the
+        // ledger fails to be recorded
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://localhost:1/milk")
+            .unwrap();
+        let buckets = Buckets {
+            ledger: Some(Ledger {
+                pool,
+                stock_litres: 0.5,
+            }),
+            ..Buckets::new(BucketConfig::default())
+        };
+        let app = router_with(buckets, day12::Games::new(DAY12_IDLE_TIMEOUT));
+        let withdraw = |body: &'static str| {
+            let mut request = Request::builder().uri("/9/milk").method("POST");
+            if !body.is_empty() {
+                request = request.header("Content-Type", "application/json");
+            }
+            app.clone().oneshot(request.body(Body::from(body)).unwrap())
+        };
+
+        // Turned away for want of stock before any tokens are taken
+        let response = withdraw("").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["RateLimit-Remaining"], "5");
+        assert_eq!(collect_body(response).await, "Not enough milk in stock\n");
+        let response = withdraw(r#"[{"litres": 0.3}, {"litres": 0.3}]"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["RateLimit-Remaining"], "5");
+
+        // Milk that couldn't be recorded goes back in stock
+        let response = withdraw(r#"[{"litres": 0.25}, {"litres": 0.25}]"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()["RateLimit-Remaining"], "3");
+        let response = withdraw(r#"{"litres": 0.5}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let response = withdraw(r#"{"litres": -1}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn no_ledger() {
+        let app = app();
+        let (status, _) = send(&app, "GET", "/9/inventory", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/9/refill",
+            r#"{"restock": {"value": 10, "unit": "litres"}}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn status_stream() {
+        let app = app();
+        send(
+            &app,
+            "POST",
+            "/9/refill",
+            r#"{"initial": 2, "max": 2, "interval_ms": 200}"#,
+        )
+        .await;
+        let json = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap();
+
+        let (status, body) = send(&app, "GET", "/9/status", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            json(&body),
+            serde_json::json!({ "client": "anonymous", "tokens": 2, "capacity": 2, "next_refill_ms": null })
+        );
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/9/status/stream")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body();
+
+        let (event, status) = next_event(&mut body).await;
+        assert_eq!(
+            (event.as_str(), status["tokens"].as_u64()),
+            ("status", Some(2))
+        );
+
+        withdraw(&app, ("X-Api-Key", "someone else")).await;
+        send(&app, "POST", "/9/milk", "").await;
+        let (event, status) = next_event(&mut body).await;
+        assert_eq!(
+            (event.as_str(), status["tokens"].as_u64()),
+            ("withdrawal", Some(1))
+        );
+        assert!(status["next_refill_ms"].as_u64().unwrap() <= 200);
+
+        let (event, status) = next_event(&mut body).await;
+        assert_eq!(
+            (event.as_str(), status["tokens"].as_u64()),
+            ("replenishment", Some(2))
+        );
+
+        send(&app, "POST", "/9/refill", "").await;
+        let (event, _) = next_event(&mut body).await;
+        assert_eq!(event, "refill");
+    }
+
+    #[tokio::test]
+    async fn status_of() {
+        let app = app();
+        for key in ["noisy", "noisy", "quiet"] {
+            withdraw(&app, ("X-Api-Key", key)).await;
+        }
+        let status = |of: String| {
+            let app = app.clone();
+            async move {
+                let uri = format!("/9/status?of={of}");
+                let (status, body) = send(&app, "GET", &uri, "").await;
+                (
+                    status,
+                    serde_json::from_str::<serde_json::Value>(&body).unwrap_or_default(),
+                )
+            }
+        };
+
+        // Clients are named without giving their keys away
+        let label = Client::api_key("noisy").to_string();
+        assert!(label.starts_with("key:") && !label.contains("noisy"));
+        let (_, noisy) = status(label.clone()).await;
+        assert_eq!(
+            (noisy["client"].as_str(), noisy["tokens"].as_u64()),
+            (Some(label.as_str()), Some(3))
+        );
+        let (_, all) = status("all".to_string()).await;
+        assert_eq!(
+            (
+                all["tokens"].as_u64(),
+                all["capacity"].as_u64(),
+                all["clients"].as_u64()
+            ),
+            (Some(7), Some(10), Some(2))
+        );
+        let (code, _) = status("cap".to_string()).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+        let (code, _) = status("nobody".to_string()).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/9/status/stream?of=all")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let mut body = response.into_body();
+        next_event(&mut body).await;
+        withdraw(&app, ("X-Api-Key", "quiet")).await;
+        let (event, status) = next_event(&mut body).await;
+        assert_eq!(
+            (event.as_str(), status["tokens"].as_u64()),
+            ("withdrawal", Some(6))
+        );
+    }
+
+    #[tokio::test]
+    async fn global_cap() {
+        let mut buckets = Buckets::new(BucketConfig::default()).with_cap(Some(BucketConfig {
+            initial: 2,
+            ..Default::default()
+        }));
+        let client = Client::api_key;
+        let withdraw = |buckets: &mut Buckets, key: &str| {
+            let (bucket, cap) = buckets.buckets_for(&client(key));
+            try_acquire(&bucket, cap.as_deref(), 1)
+        };
+
+        assert!(withdraw(&mut buckets, "a"));
+        assert!(withdraw(&mut buckets, "b"));
+        assert!(!withdraw(&mut buckets, "c"));
+        assert_eq!(buckets.quota(&client("c")).remaining, 0);
+        assert_eq!(buckets.status(&Subject::Cap).unwrap().capacity, 5);
+
+        buckets.reset(BucketConfig::default());
+        assert!(withdraw(&mut buckets, "c"));
+
+        // Without a cap, clients only run out of their own milk
+        let mut buckets = buckets.with_cap(None);
+        assert!(buckets.status(&Subject::Cap).is_none());
+        for _ in 0..4 {
+            assert!(withdraw(&mut buckets, "c"));
+        }
+        assert!(!withdraw(&mut buckets, "c"));
+        assert!(withdraw(&mut buckets, "d"));
+    }
+
+    #[tokio::test]
+    async fn waits_take_all_or_nothing() {
+        let buckets = Buckets::new(BucketConfig::default()).with_cap(Some(BucketConfig {
+            initial: 0,
+            max: 1,
+            interval_ms: 60_000,
+            refill_amount: 1,
+        }));
+        let client = Client::Anonymous;
+        let buckets = tokio::sync::RwLock::new(buckets);
+        let (bucket, _) = buckets.write().await.buckets_for(&client);
+
+        let wait = Wait(Some(Duration::from_millis(50)));
+        assert!(!wait.acquire(&buckets, &client, 1).await);
+        assert_eq!(bucket.remaining(), 5);
+        assert!(!Wait(None).acquire(&buckets, &client, 1).await);
+        assert_eq!(bucket.remaining(), 5);
+    }
+
+    #[tokio::test]
+    async fn waits_see_refills() {
+        let buckets = Buckets::new(BucketConfig {
+            initial: 0,
+            max: 1,
+            interval_ms: 60_000,
+            refill_amount: 1,
+        });
+        let client = Client::Anonymous;
+        let buckets = tokio::sync::RwLock::new(buckets);
+
+        // The next refill is long after the deadline, but a reset comes first
+        let wait = Wait(Some(Duration::from_millis(500)));
+        let (acquired, ()) = tokio::join!(wait.acquire(&buckets, &client, 1), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            buckets.write().await.reset(BucketConfig::default());
+        });
+        assert!(acquired);
+        let (bucket, _) = buckets.write().await.buckets_for(&client);
+        assert_eq!(bucket.remaining(), 4);
+    }
 }
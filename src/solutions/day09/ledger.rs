@@ -0,0 +1,108 @@
+use sqlx::{types::chrono, PgExecutor, PgPool};
+
+use super::units::{Measure, Unit};
+
+/// What a ledger entry did to the stock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Withdrawal,
+    Refill,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Withdrawal => "withdrawal",
+            Kind::Refill => "refill",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Entry {
+    id: i64,
+    recorded_at: chrono::DateTime<chrono::Utc>,
+    kind: String,
+    client: String,
+    amount: f64,
+    unit: String,
+}
+
+/// Everything the ledger has recorded, added up.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Totals {
+    pub refills: i64,
+    pub withdrawals: i64,
+    pub refilled_litres: f64,
+    pub withdrawn_litres: f64,
+}
+
+impl Totals {
+    pub fn stock_litres(&self) -> f64 {
+        self.refilled_litres - self.withdrawn_litres
+    }
+}
+
+pub async fn record(
+    executor: impl PgExecutor<'_>,
+    kind: Kind,
+    client: &str,
+    measure: Measure,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO milk_ledger (kind, client, amount, unit) VALUES ($1, $2, $3, $4)",
+        kind.name(),
+        client,
+        measure.value,
+        measure.unit.name()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// The latest `limit` entries, newest first.
+pub async fn entries(pool: &PgPool, limit: i64) -> Result<Vec<Entry>, sqlx::Error> {
+    sqlx::query_as!(
+        Entry,
+        "SELECT id, recorded_at, kind, client, amount, unit
+		FROM milk_ledger
+		ORDER BY id DESC
+		LIMIT $1",
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn totals(pool: &PgPool) -> Result<Totals, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT kind, unit, SUM(amount) AS "amount!", COUNT(*) AS "count!"
+		FROM milk_ledger
+		GROUP BY kind, unit"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut totals = Totals::default();
+    for row in rows {
+        let unit = Unit::from_name(&row.unit)
+            .ok_or_else(|| sqlx::Error::Decode(format!("Unknown unit {}", row.unit).into()))?;
+        let litres = Measure {
+            value: row.amount,
+            unit,
+        }
+        .litres();
+
+        if row.kind == Kind::Refill.name() {
+            totals.refills += row.count;
+            totals.refilled_litres += litres;
+        } else {
+            totals.withdrawals += row.count;
+            totals.withdrawn_litres += litres;
+        }
+    }
+
+    Ok(totals)
+}
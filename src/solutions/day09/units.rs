@@ -0,0 +1,149 @@
+/// A unit of volume. US and imperial units share names but not sizes, so
+/// those carry their system; unqualified names are taken as US units, except
+/// pints, which the original `{"litres": ...}` conversion made imperial.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unit {
+    #[serde(alias = "gallons")]
+    UsGallons,
+    ImperialGallons,
+    #[serde(alias = "quarts")]
+    UsQuarts,
+    ImperialQuarts,
+    UsPints,
+    #[serde(alias = "pints")]
+    ImperialPints,
+    #[serde(alias = "cups")]
+    UsCups,
+    ImperialCups,
+    #[serde(alias = "fluid_ounces")]
+    UsFluidOunces,
+    ImperialFluidOunces,
+    #[serde(alias = "milliliters")]
+    Millilitres,
+    #[serde(alias = "liters")]
+    Litres,
+}
+
+impl Unit {
+    const ALL: [Unit; 12] = [
+        Unit::UsGallons,
+        Unit::ImperialGallons,
+        Unit::UsQuarts,
+        Unit::ImperialQuarts,
+        Unit::UsPints,
+        Unit::ImperialPints,
+        Unit::UsCups,
+        Unit::ImperialCups,
+        Unit::UsFluidOunces,
+        Unit::ImperialFluidOunces,
+        Unit::Millilitres,
+        Unit::Litres,
+    ];
+
+    /// The unit with this [`Unit::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|unit| unit.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::UsGallons => "us_gallons",
+            Unit::ImperialGallons => "imperial_gallons",
+            Unit::UsQuarts => "us_quarts",
+            Unit::ImperialQuarts => "imperial_quarts",
+            Unit::UsPints => "us_pints",
+            Unit::ImperialPints => "imperial_pints",
+            Unit::UsCups => "us_cups",
+            Unit::ImperialCups => "imperial_cups",
+            Unit::UsFluidOunces => "us_fluid_ounces",
+            Unit::ImperialFluidOunces => "imperial_fluid_ounces",
+            Unit::Millilitres => "millilitres",
+            Unit::Litres => "litres",
+        }
+    }
+
+    /// Size of the unit, by its exact legal definition.
+    fn millilitres(self) -> f64 {
+        match self {
+            Unit::UsGallons => 3_785.411_784,
+            Unit::ImperialGallons => 4_546.09,
+            Unit::UsQuarts => 946.352_946,
+            Unit::ImperialQuarts => 1_136.522_5,
+            Unit::UsPints => 473.176_473,
+            Unit::ImperialPints => 568.261_25,
+            Unit::UsCups => 236.588_236_5,
+            Unit::ImperialCups => 284.130_625,
+            Unit::UsFluidOunces => 29.573_529_562_5,
+            Unit::ImperialFluidOunces => 28.413_062_5,
+            Unit::Millilitres => 1.0,
+            Unit::Litres => 1_000.0,
+        }
+    }
+}
+
+/// An amount of milk.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Measure {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Measure {
+    pub fn litres(&self) -> f64 {
+        self.value * self.unit.millilitres() / Unit::Litres.millilitres()
+    }
+}
+
+/// A request to convert `value` between two units of volume.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Conversion {
+    pub from: Unit,
+    pub to: Unit,
+    pub value: f64,
+}
+
+impl Conversion {
+    pub fn result(&self) -> f64 {
+        self.value * self.from.millilitres() / self.to.millilitres()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conversion, Unit};
+
+    fn convert(value: f64, from: Unit, to: Unit) -> f64 {
+        Conversion { from, to, value }.result()
+    }
+
+    #[test]
+    fn exact_factors() {
+        assert_eq!(convert(1.0, Unit::UsGallons, Unit::UsCups), 16.0);
+        assert_eq!(
+            convert(1.0, Unit::ImperialGallons, Unit::ImperialPints),
+            8.0
+        );
+        assert_eq!(convert(1.0, Unit::UsCups, Unit::UsFluidOunces), 8.0);
+        assert_eq!(
+            convert(1.0, Unit::ImperialPints, Unit::ImperialFluidOunces),
+            20.0
+        );
+        assert_eq!(convert(2.5, Unit::Litres, Unit::Millilitres), 2_500.0);
+    }
+
+    #[test]
+    fn names() {
+        for unit in Unit::ALL {
+            assert_eq!(Unit::from_name(unit.name()), Some(unit));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let there = convert(3.0, Unit::UsQuarts, Unit::ImperialQuarts);
+        assert!((convert(there, Unit::ImperialQuarts, Unit::UsQuarts) - 3.0).abs() < 1e-12);
+    }
+}
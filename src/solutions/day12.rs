@@ -1,14 +1,53 @@
-use std::{fmt::Display, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension};
+use analysis::Direction;
+use axum::{
+    extract::{
+        ws::{Message, WebSocketUpgrade},
+        FromRequestParts, Path, Query, RawPathParams,
+    },
+    http::{
+        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+        request::Parts,
+        HeaderMap, StatusCode,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension, Json,
+};
 use itertools::Itertools;
 use rand::{Rng, SeedableRng};
-use tokio::sync::RwLock;
+use serde_json::json;
+use sqlx::{
+    types::{
+        chrono,
+        uuid::{self, Uuid},
+    },
+    PgPool,
+};
+use tokio::sync::{watch, RwLock};
 
-type BoardLock = Extension<Arc<RwLock<Board>>>;
+mod ai;
+mod analysis;
+mod notation;
+mod stats;
+mod store;
+mod svg;
+
+type BoardLock = Arc<RwLock<Board>>;
+type GamesLock = Extension<Arc<RwLock<Games>>>;
 type RandLock = Extension<Arc<RwLock<rand::rngs::StdRng>>>;
 
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Cell {
     #[default]
     Empty,
@@ -27,6 +66,30 @@ impl Display for Cell {
 }
 
 impl Cell {
+    fn team(name: &str) -> Option<Self> {
+        match name {
+            "cookie" => Some(Cell::Cookie),
+            "milk" => Some(Cell::Milk),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Cell::Empty => "empty",
+            Cell::Cookie => "cookie",
+            Cell::Milk => "milk",
+        }
+    }
+
+    fn opponent(&self) -> Self {
+        match self {
+            Cell::Cookie => Cell::Milk,
+            Cell::Milk => Cell::Cookie,
+            Cell::Empty => Cell::Empty,
+        }
+    }
+
     fn winner(&self) -> String {
         match self {
             Cell::Cookie => "🍪 wins!",
@@ -37,28 +100,165 @@ impl Cell {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Status {
     #[default]
     InProgress,
     Winner(Cell),
 }
 
-#[derive(Default)]
-pub struct Board([[Cell; 4]; 4], Status);
+/// Rules for where pieces go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    #[default]
+    Standard,
+    /// Teams may also pop one of their own pieces out of the bottom of a
+    /// column, dropping the pieces above it. A full board is only a draw
+    /// when the team to move has nothing of theirs to pop.
+    PopOut,
+    /// Pieces stay where they are put, so any empty cell can be claimed.
+    NoGravity,
+}
+
+impl Variant {
+    /// Checks that this variant has moves of `action`, with a row chosen or
+    /// not.
+    fn allows(self, action: Action, row: Option<usize>) -> Result<(), &'static str> {
+        match (action, row) {
+            (Action::Pop, _) if self != Variant::PopOut => Err("only Pop Out games allow pops"),
+            (Action::Pop, Some(_)) => Err("pops take a column, not a row"),
+            (Action::Drop, None) if self == Variant::NoGravity => {
+                Err("pieces don't fall, so a row is needed")
+            }
+            (Action::Drop, Some(_)) if self != Variant::NoGravity => {
+                Err("pieces fall, so rows can't be chosen")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Dimensions, win condition and turn rules of a board, chosen when a game
+/// is created.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct BoardConfig {
+    pub width: usize,
+    pub height: usize,
+    pub connect: usize,
+    /// Teams must alternate moves.
+    pub strict: bool,
+    pub variant: Variant,
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        Self {
+            width: 4,
+            height: 4,
+            connect: 4,
+            strict: false,
+            variant: Variant::Standard,
+        }
+    }
+}
+
+impl BoardConfig {
+    const MAX_SIDE: usize = 32;
+
+    fn validate(&self) -> Result<(), String> {
+        if !(1..=Self::MAX_SIDE).contains(&self.width)
+            || !(1..=Self::MAX_SIDE).contains(&self.height)
+        {
+            return Err(format!(
+                "Board sides must be between 1 and {}",
+                Self::MAX_SIDE
+            ));
+        }
+        if self.connect < 1 || self.connect > self.width.max(self.height) {
+            return Err("Win length must fit on the board".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What a move does to its column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Drop,
+    /// Takes the team's own piece out of the bottom of the column.
+    Pop,
+}
+
+/// A piece placed on, or popped off, the board. `row` and `column` are
+/// zero-based grid indices, with row 0 at the top.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Move {
+    team: Cell,
+    #[serde(default)]
+    action: Action,
+    column: usize,
+    row: usize,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl Move {
+    /// The move as clients see it: one-based columns like `place` takes, and
+    /// rows counted one-based from the bottom of the board.
+    fn to_json(&self, height: usize) -> serde_json::Value {
+        json!({
+            "team": self.team,
+            "action": self.action,
+            "column": self.column + 1,
+            "row": height - self.row,
+            "timestamp": self.timestamp,
+        })
+    }
+}
+
+/// Someone who joined a team, proving it with `token` on each move.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Player {
+    token: Uuid,
+    /// Shown on the leaderboard.
+    name: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Board {
+    grid: Vec<Vec<Cell>>,
+    status: Status,
+    config: BoardConfig,
+    /// The team due to move, unknown until the first piece is placed.
+    next: Option<Cell>,
+    /// The players that joined each team. A team with a player only accepts
+    /// moves that present the player's token.
+    players: HashMap<Cell, Player>,
+    moves: Vec<Move>,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new(BoardConfig::default())
+    }
+}
 
 impl Display for Board {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for row in self.0.iter() {
+        for row in self.grid.iter() {
             write!(f, "⬜")?;
             for cell in row {
                 write!(f, "{}", cell)?;
             }
             writeln!(f, "⬜")?;
         }
-        writeln!(f, "⬜⬜⬜⬜⬜⬜")?;
+        writeln!(f, "{}", "⬜".repeat(self.width() + 2))?;
 
-        match &self.1 {
+        match &self.status {
             Status::InProgress => (),
             Status::Winner(winner) => {
                 writeln!(f, "{}", winner.winner())?;
@@ -70,8 +270,239 @@ impl Display for Board {
 }
 
 impl Board {
+    pub fn new(config: BoardConfig) -> Self {
+        Self {
+            grid: vec![vec![Cell::Empty; config.width]; config.height],
+            status: Status::InProgress,
+            config,
+            next: None,
+            players: HashMap::new(),
+            moves: Vec::new(),
+        }
+    }
+
+    /// Clears the board for a new round, keeping its config and players.
+    fn reset(&mut self) {
+        let players = std::mem::take(&mut self.players);
+        *self = Board {
+            players,
+            ..Board::new(self.config)
+        };
+    }
+
+    fn width(&self) -> usize {
+        self.config.width
+    }
+
+    fn height(&self) -> usize {
+        self.config.height
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.status, Status::Winner(_))
+    }
+
+    /// Checks that `team` may move now: the request must carry the team's
+    /// player token if one was issued, and in strict games it must be the
+    /// team's turn.
+    fn authorize(&self, team: &Cell, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+        self.authorize_player(team, headers)?;
+        self.check_turn(team)
+    }
+
+    fn check_turn(&self, team: &Cell) -> Result<(), (StatusCode, String)> {
+        match &self.next {
+            Some(next) if self.config.strict && next != team => {
+                Err((StatusCode::CONFLICT, format!("It is {next}'s turn\n")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the request carries `team`'s player token, if one was
+    /// issued.
+    fn authorize_player(
+        &self,
+        team: &Cell,
+        headers: &HeaderMap,
+    ) -> Result<(), (StatusCode, String)> {
+        if let Some(Player {
+            token: expected, ..
+        }) = self.players.get(team)
+        {
+            match bearer_token(headers) {
+                None => {
+                    return Err((
+                        StatusCode::UNAUTHORIZED,
+                        format!("{team} needs a player token\n"),
+                    ))
+                }
+                Some(token) if token.parse::<Uuid>().ok().as_ref() != Some(expected) => {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        format!("Wrong player token for {team}\n"),
+                    ))
+                }
+                Some(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the request carries one of the players' tokens, if any
+    /// joined, for changes that affect both teams.
+    fn authorize_any_player(&self, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+        if self.players.is_empty() {
+            return Ok(());
+        }
+
+        let Some(token) = bearer_token(headers) else {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "A player token is needed\n".to_string(),
+            ));
+        };
+        let token = token.parse::<Uuid>().ok();
+        if !self
+            .players
+            .values()
+            .any(|player| Some(player.token) == token)
+        {
+            return Err((StatusCode::FORBIDDEN, "Wrong player token\n".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Fills a default board with random pieces, row by row from the top,
+    /// and declares whoever completed a line last the winner.
+    fn random(random_nums: &mut impl Rng) -> Self {
+        let mut board = Board::default();
+        let mut winner = Cell::Empty;
+        for i in 0..board.height() {
+            for j in 0..board.width() {
+                board.grid[i][j] = if random_nums.gen::<bool>() {
+                    Cell::Cookie
+                } else {
+                    Cell::Milk
+                };
+                match board.check(i, j) {
+                    Status::InProgress => (),
+                    Status::Winner(curr_winner) => {
+                        winner = curr_winner;
+                    }
+                }
+            }
+        }
+
+        board.status = Status::Winner(winner);
+        board
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let (status, winner) = match &self.status {
+            Status::InProgress => ("in_progress", None),
+            Status::Winner(Cell::Empty) => ("draw", None),
+            Status::Winner(winner) => ("won", Some(winner)),
+        };
+
+        json!({
+            "grid": self.grid,
+            "status": status,
+            "winner": winner,
+            "next": self.next,
+            "moves": self.moves.len(),
+        })
+    }
+
+    fn render(&self, format: Format) -> Response {
+        match format {
+            Format::Text => self.to_string().into_response(),
+            Format::Json => Json(self.to_json()).into_response(),
+            Format::Svg => {
+                ([(CONTENT_TYPE, "image/svg+xml")], self.rendered(format)).into_response()
+            }
+        }
+    }
+
+    /// The body [`Board::render`] would respond with, for pushing to streams.
+    fn rendered(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_string(),
+            Format::Json => self.to_json().to_string(),
+            Format::Svg => svg::Svg(self).to_string(),
+        }
+    }
+
+    /// Plays `action` for `team` in `col`, records the move and updates the
+    /// status. `row` is the cell to claim in no-gravity games, which
+    /// [`Variant::allows`] has checked. Returns why the move can't be played
+    /// in this position, if it can't.
+    fn apply(
+        &mut self,
+        team: Cell,
+        action: Action,
+        col: usize,
+        row: Option<usize>,
+    ) -> Result<usize, &'static str> {
+        let row = match (action, row) {
+            (Action::Drop, None) => self.insert(team.clone(), col).ok_or("the column is full")?,
+            (Action::Drop, Some(row)) => self
+                .claim(team.clone(), row, col)
+                .ok_or("the cell is taken")?,
+            (Action::Pop, _) => self
+                .pop(&team, col)
+                .ok_or("the bottom piece isn't theirs")?,
+        };
+
+        let played = Move {
+            team,
+            action,
+            column: col,
+            row,
+            timestamp: chrono::Utc::now(),
+        };
+        self.status = self.status_after(&played);
+        self.next = Some(played.team.opponent());
+        self.moves.push(played);
+        Ok(row)
+    }
+
+    /// Takes back the last move. Only the last move of a game can have
+    /// decided it, so the status is recomputed from the one before.
+    fn undo(&mut self) -> Option<Move> {
+        let undone = self.moves.pop()?;
+        match undone.action {
+            Action::Drop => self.grid[undone.row][undone.column] = Cell::Empty,
+            Action::Pop => {
+                for row in 0..self.height() - 1 {
+                    self.grid[row][undone.column] = self.grid[row + 1][undone.column].clone();
+                }
+                self.grid[undone.row][undone.column] = undone.team.clone();
+            }
+        }
+
+        self.status = match self.moves.last() {
+            Some(last) => self.status_after(last),
+            None => Status::InProgress,
+        };
+        self.next = (!self.moves.is_empty()).then(|| undone.team.clone());
+        Some(undone)
+    }
+
+    /// Status of the board right after `played`.
+    fn status_after(&self, played: &Move) -> Status {
+        match played.action {
+            Action::Drop => self.check(played.row, played.column),
+            Action::Pop => self.check_pop(&played.team, played.column),
+        }
+    }
+
+    /// Drops `team`'s piece down `col`, returning the row it lands in, or
+    /// `None` if the column is full.
     fn insert(&mut self, team: Cell, col: usize) -> Option<usize> {
-        let board = &mut self.0;
+        let board = &mut self.grid;
 
         for (i, row) in board.iter_mut().enumerate().rev() {
             let next_cell = &mut row[col];
@@ -84,108 +515,1670 @@ impl Board {
         None
     }
 
-    fn check(&self, row: usize, col: usize) -> Status {
-        let board = &self.0;
-
-        if board[row][0] != Cell::Empty && board[row].iter().all_equal() {
-            return Status::Winner(board[row][col].clone());
+    /// Puts `team`'s piece in a cell of a no-gravity board, returning its
+    /// row, or `None` if the cell is taken.
+    fn claim(&mut self, team: Cell, row: usize, col: usize) -> Option<usize> {
+        let cell = &mut self.grid[row][col];
+        if *cell != Cell::Empty {
+            return None;
         }
-        if board[0][col] != Cell::Empty && board.iter().map(|row| &row[col]).all_equal() {
-            return Status::Winner(board[row][col].clone());
+
+        *cell = team;
+        Some(row)
+    }
+
+    /// Takes `team`'s piece out of the bottom of `col`, moving the rest of
+    /// the column down a row. Returns the bottom row, or `None` if the piece
+    /// there isn't `team`'s.
+    fn pop(&mut self, team: &Cell, col: usize) -> Option<usize> {
+        let bottom = self.height() - 1;
+        if self.grid[bottom][col] != *team {
+            return None;
         }
-        if row == col
-            && board[0][0] != Cell::Empty
-            && board.iter().enumerate().map(|(i, row)| &row[i]).all_equal()
-        {
-            return Status::Winner(board[row][col].clone());
+
+        for row in (1..=bottom).rev() {
+            self.grid[row][col] = self.grid[row - 1][col].clone();
         }
+        self.grid[0][col] = Cell::Empty;
+        Some(bottom)
+    }
 
-        if row + col == 3
-            && board[0][3] != Cell::Empty
-            && (0..4)
-                .rev()
-                .zip(board.iter())
-                .map(|(i, row)| &row[i])
-                .all_equal()
+    /// Length of the run of `board[row][col]`'s team along `(d_row, d_col)`,
+    /// counting the cell itself and extending in both directions.
+    fn run_length(&self, row: usize, col: usize, (d_row, d_col): (isize, isize)) -> usize {
+        let team = &self.grid[row][col];
+        let count = |sign: isize| {
+            (1..)
+                .map(|step| {
+                    let row = row.checked_add_signed(sign * step * d_row)?;
+                    let col = col.checked_add_signed(sign * step * d_col)?;
+                    self.grid.get(row)?.get(col)
+                })
+                .take_while(|cell| *cell == Some(team))
+                .count()
+        };
+
+        1 + count(1) + count(-1)
+    }
+
+    fn check(&self, row: usize, col: usize) -> Status {
+        let board = &self.grid;
+
+        if board[row][col] != Cell::Empty
+            && Direction::ALL.into_iter().any(|direction| {
+                self.run_length(row, col, direction.delta()) >= self.config.connect
+            })
         {
             return Status::Winner(board[row][col].clone());
         }
 
-        if row == 0 && !board[0].iter().any(|cell| *cell == Cell::Empty) {
+        let full = match self.config.variant {
+            Variant::Standard => row == 0 && !board[0].contains(&Cell::Empty),
+            // Only drops fill the board, and the other team moves next
+            Variant::PopOut => {
+                !board[0].contains(&Cell::Empty)
+                    && !board[self.height() - 1].contains(&board[row][col].opponent())
+            }
+            Variant::NoGravity => board.iter().flatten().all(|cell| *cell != Cell::Empty),
+        };
+        if full {
             return Status::Winner(Cell::Empty);
         }
 
         Status::InProgress
     }
+
+    /// Status after `team` popped a piece out of `col`. Every piece in the
+    /// column moved, so any of them may have completed a line, possibly for
+    /// both teams; then the team that popped wins.
+    fn check_pop(&self, team: &Cell, col: usize) -> Status {
+        let winners = (0..self.height())
+            .filter_map(|row| match self.check(row, col) {
+                Status::Winner(winner) if winner != Cell::Empty => Some(winner),
+                _ => None,
+            })
+            .unique()
+            .collect_vec();
+
+        if winners.contains(team) {
+            Status::Winner(team.clone())
+        } else {
+            winners
+                .into_iter()
+                .next()
+                .map_or(Status::InProgress, Status::Winner)
+        }
+    }
+
+    /// Rejects requests to the computer opponent or the analysis on boards
+    /// without gravity, whose moves pick cells rather than columns.
+    fn check_gravity(&self) -> Result<(), (StatusCode, &'static str)> {
+        if self.config.variant == Variant::NoGravity {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Only boards with gravity can be searched\n",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sets up a position that didn't come from moves played here, keeping
+    /// `config`'s rules but taking the size from `grid`. The position must be
+    /// reachable by real play under those rules: no piece floating over an
+    /// empty cell unless there's no gravity, the teams' piece counts at most
+    /// one apart unless pops could have evened them out, and at most one team
+    /// with a line.
+    fn from_position(grid: Vec<Vec<Cell>>, config: BoardConfig) -> Result<Self, String> {
+        let config = BoardConfig {
+            width: grid.first().map_or(0, Vec::len),
+            height: grid.len(),
+            ..config
+        };
+        config.validate()?;
+        if grid.iter().any(|row| row.len() != config.width) {
+            return Err("Rows must all be the same width".to_string());
+        }
+
+        for col in (0..config.width).filter(|_| config.variant != Variant::NoGravity) {
+            let floating = grid
+                .iter()
+                .map(|row| &row[col])
+                .skip_while(|cell| **cell == Cell::Empty)
+                .any(|cell| *cell == Cell::Empty);
+            if floating {
+                return Err(format!("Column {} has a piece over an empty cell", col + 1));
+            }
+        }
+
+        let count = |team: Cell| grid.iter().flatten().filter(|cell| **cell == team).count();
+        let (cookies, milks) = (count(Cell::Cookie), count(Cell::Milk));
+        if config.variant != Variant::PopOut && cookies.abs_diff(milks) > 1 {
+            return Err(format!(
+                "{cookies} 🍪 and {milks} 🥛 can't come from alternating moves"
+            ));
+        }
+
+        let mut board = Board::new(config);
+        board.grid = grid;
+        board.next = match cookies.cmp(&milks) {
+            _ if config.variant == Variant::PopOut => None,
+            std::cmp::Ordering::Greater => Some(Cell::Milk),
+            std::cmp::Ordering::Less => Some(Cell::Cookie),
+            std::cmp::Ordering::Equal => None,
+        };
+
+        let winners = board
+            .lines()
+            .into_iter()
+            .map(|line| line.team)
+            .unique()
+            .collect_vec();
+        board.status = match winners.as_slice() {
+            [] if config.variant != Variant::PopOut
+                && board.grid.iter().flatten().all(|cell| *cell != Cell::Empty) =>
+            {
+                Status::Winner(Cell::Empty)
+            }
+            [] => Status::InProgress,
+            [winner] => Status::Winner(winner.clone()),
+            _ => return Err("Both teams can't have a line".to_string()),
+        };
+
+        Ok(board)
+    }
+}
+
+/// The token in an `Authorization: Bearer <token>` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+}
+
+fn random_uuid() -> Uuid {
+    uuid::Builder::from_random_bytes(rand::random()).into_uuid()
+}
+
+/// Every game being played, keyed by id, plus the "default" game served by
+/// the bare `/12/board` routes. Games that go untouched for longer than
+/// `idle_timeout` are evicted the next time the registry is accessed.
+///
+/// Games built with [`Games::restore`] are also kept in Postgres, so they
+/// outlive the process.
+pub struct Games {
+    default: Game,
+    games: HashMap<Uuid, (Game, Instant)>,
+    idle_timeout: Duration,
+    store: Option<PgPool>,
+    summary: stats::Summary,
+    /// The result last recorded for each live game, to take back out of
+    /// `summary` if its winning move is undone.
+    recorded: HashMap<Uuid, stats::GameResult>,
+}
+
+impl Games {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            default: Game::new(None, Board::default(), None),
+            games: HashMap::new(),
+            idle_timeout,
+            store: None,
+            summary: stats::Summary::default(),
+            recorded: HashMap::new(),
+        }
+    }
+
+    /// Loads the games saved in `pool` that haven't gone idle, and saves
+    /// every change made from here on back to it.
+    pub async fn restore(pool: PgPool, idle_timeout: Duration) -> Result<Self, sqlx::Error> {
+        let mut games = Self {
+            default: Game::new(None, Board::default(), Some(pool.clone())),
+            store: Some(pool.clone()),
+            ..Self::new(idle_timeout)
+        };
+        for (id, result) in store::load_results(&pool).await? {
+            games.summary.add(&result);
+            if let Some(id) = id {
+                games.recorded.insert(id, result);
+            }
+        }
+        for (id, board) in store::load(&pool, idle_timeout).await? {
+            if id == store::DEFAULT_GAME_ID {
+                // Players could once join the default game and lock others out
+                let board = Board {
+                    players: HashMap::new(),
+                    ..board
+                };
+                games.default = Game::new(None, board, Some(pool.clone()));
+            } else {
+                let game = Game::new(Some(id), board, Some(pool.clone()));
+                games.games.insert(id, (game, Instant::now()));
+            }
+        }
+        let live = |id: &Uuid| *id == store::DEFAULT_GAME_ID || games.games.contains_key(id);
+        games.recorded = std::mem::take(&mut games.recorded)
+            .into_iter()
+            .filter(|(id, _)| live(id))
+            .collect();
+
+        Ok(games)
+    }
+
+    /// Records the result of `game` for the leaderboard, if `board` has
+    /// just finished it.
+    async fn record(
+        &mut self,
+        game: &Game,
+        board: &Board,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        let Some(result) = stats::GameResult::of(board) else {
+            return Ok(());
+        };
+        if let Some(pool) = &self.store {
+            store::save_result(pool, game.key(), &result)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Could not save result\n"))?;
+        }
+
+        self.summary.add(&result);
+        self.recorded.insert(game.key(), result);
+        Ok(())
+    }
+
+    /// Takes the result recorded for `game` back off the leaderboard, once
+    /// the move that finished it has been undone.
+    async fn unrecord(&mut self, game: &Game) -> Result<(), (StatusCode, &'static str)> {
+        let Some(result) = self.recorded.remove(&game.key()) else {
+            return Ok(());
+        };
+        if let Some(pool) = &self.store {
+            store::delete_result(pool, game.key())
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Could not save result\n"))?;
+        }
+
+        self.summary.remove(&result);
+        Ok(())
+    }
+
+    async fn evict_idle(&mut self) -> Result<(), sqlx::Error> {
+        let idle_timeout = self.idle_timeout;
+        let idle = self
+            .games
+            .iter()
+            .filter(|(_, (_, last_active))| last_active.elapsed() >= idle_timeout)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        if idle.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(pool) = &self.store {
+            store::delete(pool, &idle).await?;
+        }
+        for id in idle {
+            self.games.remove(&id);
+            self.recorded.remove(&id);
+        }
+        Ok(())
+    }
+
+    async fn create(&mut self, config: BoardConfig) -> Result<Uuid, sqlx::Error> {
+        self.evict_idle().await?;
+
+        let id = random_uuid();
+        let board = Board::new(config);
+        if let Some(pool) = &self.store {
+            store::save(pool, id, &board).await?;
+        }
+
+        let game = Game::new(Some(id), board, self.store.clone());
+        self.games.insert(id, (game, Instant::now()));
+        Ok(id)
+    }
+
+    async fn get(&mut self, id: &Uuid) -> Result<Option<Game>, sqlx::Error> {
+        self.evict_idle().await?;
+
+        let Some((game, last_active)) = self.games.get_mut(id) else {
+            return Ok(None);
+        };
+        *last_active = Instant::now();
+        Ok(Some(game.clone()))
+    }
+}
+
+/// The game a request operates on: the one named by the `:id` path
+/// parameter, or the default game when the route has none.
+#[derive(Clone)]
+pub struct Game {
+    id: Option<Uuid>,
+    board: BoardLock,
+    /// Ticks every time the board changes, for spectators to follow along.
+    updates: Arc<watch::Sender<()>>,
+    store: Option<PgPool>,
+}
+
+impl Game {
+    fn new(id: Option<Uuid>, board: Board, store: Option<PgPool>) -> Self {
+        Self {
+            id,
+            board: Arc::new(RwLock::new(board)),
+            updates: Arc::new(watch::Sender::new(())),
+            store,
+        }
+    }
+
+    /// The id the game is stored under.
+    fn key(&self) -> Uuid {
+        self.id.unwrap_or(store::DEFAULT_GAME_ID)
+    }
+
+    /// Replaces `board` with `updated` and tells spectators that it changed.
+    /// When games are being stored, `updated` is saved first, and `board`
+    /// is left alone if that fails.
+    async fn commit(
+        &self,
+        board: &mut Board,
+        updated: Board,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        if let Some(pool) = &self.store {
+            store::save(pool, self.key(), &updated)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Could not save game\n"))?;
+        }
+
+        *board = updated;
+        self.updates.send_replace(());
+        Ok(())
+    }
+
+    /// Commits `updated`, a move made on `board`, putting the result on the
+    /// leaderboard first if the move finishes the game. The result comes off
+    /// again if the move can't be saved, so a move is never made without
+    /// its result being recorded.
+    async fn play(
+        &self,
+        games: &RwLock<Games>,
+        board: &mut Board,
+        updated: Board,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        let finishes = updated.is_finished();
+        games.write().await.record(self, &updated).await?;
+        if let Err(rejection) = self.commit(board, updated).await {
+            if finishes {
+                // Nothing more to tell the client if this fails too
+                let _ = games.write().await.unrecord(self).await;
+            }
+            return Err(rejection);
+        }
+        Ok(())
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Game {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(games) = Extension::<Arc<RwLock<Games>>>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let params = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let Some((_, id)) = params.iter().find(|(key, _)| *key == "id") else {
+            return Ok(games.read().await.default.clone());
+        };
+
+        let Ok(id) = id.parse::<Uuid>() else {
+            return Err(StatusCode::BAD_REQUEST.into_response());
+        };
+        let game = games.write().await.get(&id).await;
+        match game {
+            Ok(Some(game)) => Ok(game),
+            Ok(None) => Err(StatusCode::NOT_FOUND.into_response()),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+        }
+    }
+}
+
+/// How a board should be rendered, chosen by a `format` query parameter or
+/// else negotiated from the `Accept` header. Plain emoji text is the default.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Json,
+    Svg,
+}
+
+#[derive(serde::Deserialize)]
+struct FormatQuery {
+    format: Option<Format>,
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Ok(Query(FormatQuery { format })) = Query::try_from_uri(&parts.uri) else {
+            return Err((StatusCode::BAD_REQUEST, "Unknown format\n"));
+        };
+        if let Some(format) = format {
+            return Ok(format);
+        }
+
+        let accepts = |mime: &str| {
+            parts
+                .headers
+                .get_all(ACCEPT)
+                .iter()
+                .filter_map(|header| header.to_str().ok())
+                .flat_map(|header| header.split(','))
+                .any(|range| range.split(';').next().map(str::trim) == Some(mime))
+        };
+
+        if accepts("application/json") {
+            Ok(Format::Json)
+        } else if accepts("image/svg+xml") {
+            Ok(Format::Svg)
+        } else {
+            Ok(Format::Text)
+        }
+    }
+}
+
+pub async fn create_game(Extension(games): GamesLock, body: String) -> impl IntoResponse {
+    let config = if body.trim().is_empty() {
+        BoardConfig::default()
+    } else {
+        match serde_json::from_str::<BoardConfig>(&body) {
+            Ok(config) => config,
+            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid board config").into_response(),
+        }
+    };
+    if let Err(message) = config.validate() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let Ok(id) = games.write().await.create(config).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    (StatusCode::CREATED, Json(json!({ "id": id }))).into_response()
+}
+
+pub async fn board(game: Game, format: Format) -> Response {
+    let board = game.board.read().await;
+    board.render(format)
+}
+
+/// Server-sent events carrying the rendered board, first as it stands and
+/// then again after every change.
+pub async fn stream(game: Game, format: Format) -> impl IntoResponse {
+    let updates = game.updates.subscribe();
+    let events = futures_util::stream::unfold(
+        (game, updates, true),
+        move |(game, mut updates, first)| async move {
+            if !first {
+                updates.changed().await.ok()?;
+            }
+            let board = game.board.read().await.rendered(format);
+
+            Some((
+                Ok::<_, Infallible>(Event::default().data(board)),
+                (game, updates, false),
+            ))
+        },
+    );
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// The same updates as [`stream`], as WebSocket text messages. Anything the
+/// client sends is ignored; the socket closes when the client does.
+pub async fn watch(game: Game, format: Format, ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.on_upgrade(move |mut socket| async move {
+        let mut updates = game.updates.subscribe();
+        loop {
+            let board = game.board.read().await.rendered(format);
+            if socket.send(Message::Text(board)).await.is_err() {
+                return;
+            }
+
+            loop {
+                tokio::select! {
+                    changed = updates.changed() => match changed {
+                        Ok(()) => break,
+                        Err(_) => return,
+                    },
+                    message = socket.recv() => match message {
+                        Some(Ok(_)) => (),
+                        _ => return,
+                    },
+                }
+            }
+        }
+    })
+}
+
+/// Starts a new round. Once players have joined, only they may.
+pub async fn reset(
+    game: Game,
+    format: Format,
+    Extension(random_nums): RandLock,
+    headers: HeaderMap,
+) -> Response {
+    let mut board = game.board.write().await;
+    if let Err(rejection) = board.authorize_any_player(&headers) {
+        return rejection.into_response();
+    }
+
+    if game.id.is_none() {
+        let mut random_nums = random_nums.write().await;
+        *random_nums = rand::rngs::StdRng::seed_from_u64(2024);
+    }
+
+    let mut updated = board.clone();
+    updated.reset();
+    if let Err(rejection) = game.commit(&mut board, updated).await {
+        return rejection.into_response();
+    }
+    board.render(format)
 }
 
-pub async fn board(Extension(board): BoardLock) -> String {
-    let board = board.read().await;
-    board.to_string()
+/// Replaces the board with a position given in either emoji or compact
+/// notation. Players and rules stay; the move history starts over. Once
+/// players have joined, only they may.
+pub async fn import(game: Game, format: Format, headers: HeaderMap, body: String) -> Response {
+    let mut board = game.board.write().await;
+    if let Err(rejection) = board.authorize_any_player(&headers) {
+        return rejection.into_response();
+    }
+
+    let imported = notation::parse(&body).and_then(|grid| Board::from_position(grid, board.config));
+    let mut imported = match imported {
+        Ok(imported) => imported,
+        Err(message) => return (StatusCode::BAD_REQUEST, format!("{message}\n")).into_response(),
+    };
+    imported.players = board.players.clone();
+    if let Err(rejection) = game.commit(&mut board, imported).await {
+        return rejection.into_response();
+    }
+    board.render(format)
 }
 
-pub async fn reset(Extension(board): BoardLock, Extension(random_nums): RandLock) -> String {
-    let mut random_nums = random_nums.write().await;
-    *random_nums = rand::rngs::StdRng::seed_from_u64(2024);
-    drop(random_nums);
+#[derive(serde::Deserialize)]
+pub struct Placement {
+    team: String,
+    column: usize,
+}
 
-    let mut board = board.write().await;
-    *board = Default::default();
-    board.to_string()
+#[derive(serde::Deserialize)]
+pub struct PlaceOptions {
+    #[serde(default)]
+    action: Action,
+    /// The cell to claim on boards without gravity, counted one-based from
+    /// the bottom.
+    row: Option<usize>,
 }
 
+/// Drops `team`'s piece into `column`, or with `?action=pop` pops one of its
+/// pieces out of the bottom of it.
 pub async fn place(
-    Path((team, column)): Path<(String, usize)>,
-    Extension(board): BoardLock,
+    Path(Placement { team, column }): Path<Placement>,
+    Query(PlaceOptions { action, row }): Query<PlaceOptions>,
+    game: Game,
+    Extension(games): GamesLock,
+    format: Format,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    if !matches!(column, 1..=4) {
+    let mut board = game.board.write().await;
+    if !(1..=board.width()).contains(&column)
+        || row.is_some_and(|row| !(1..=board.height()).contains(&row))
+    {
         return StatusCode::BAD_REQUEST.into_response();
     }
+    if let Err(message) = board.config.variant.allows(action, row) {
+        return (StatusCode::BAD_REQUEST, format!("{message}\n")).into_response();
+    }
     let column = column - 1;
-    let mut board = board.write().await;
-    match board.1 {
-        Status::Winner(_) => {
-            return (StatusCode::SERVICE_UNAVAILABLE, board.to_string()).into_response()
+    let row = row.map(|row| board.height() - row);
+    if board.is_finished() {
+        return (StatusCode::SERVICE_UNAVAILABLE, board.render(format)).into_response();
+    }
+
+    let Some(team) = Cell::team(&team) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if let Err(rejection) = board.authorize(&team, &headers) {
+        return rejection.into_response();
+    }
+
+    let mut updated = board.clone();
+    if updated.apply(team, action, column, row).is_err() {
+        return (StatusCode::SERVICE_UNAVAILABLE, board.render(format)).into_response();
+    }
+    if let Err(rejection) = game.play(&games, &mut board, updated).await {
+        return rejection.into_response();
+    }
+    board.render(format)
+}
+
+#[derive(serde::Deserialize)]
+pub struct JoinAs {
+    name: Option<String>,
+}
+
+/// Binds `team` to a new player, returning the token that player must send
+/// as `Authorization: Bearer <token>` with each of their moves. Players who
+/// give a `name` are ranked on the leaderboard.
+///
+/// Only games made with `POST /12/games` can be joined: the default game is
+/// shared by everyone, so nobody may lock the others out of it.
+pub async fn join(
+    Path(TeamPath { team }): Path<TeamPath>,
+    Query(JoinAs { name }): Query<JoinAs>,
+    game: Game,
+) -> impl IntoResponse {
+    let Some(team_cell) = Cell::team(&team) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if game.id.is_none() {
+        return (
+            StatusCode::FORBIDDEN,
+            "Only games made with POST /12/games can be joined\n",
+        )
+            .into_response();
+    }
+
+    let mut board = game.board.write().await;
+    if board.players.contains_key(&team_cell) {
+        return (
+            StatusCode::CONFLICT,
+            format!("{team_cell} already has a player\n"),
+        )
+            .into_response();
+    }
+
+    let token = random_uuid();
+    let mut updated = board.clone();
+    updated.players.insert(team_cell, Player { token, name });
+    if let Err(rejection) = game.commit(&mut board, updated).await {
+        return rejection.into_response();
+    }
+    (
+        StatusCode::CREATED,
+        Json(json!({ "team": team, "token": token })),
+    )
+        .into_response()
+}
+
+pub async fn undo(
+    game: Game,
+    Extension(games): GamesLock,
+    format: Format,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let mut board = game.board.write().await;
+    let Some(last) = board.moves.last() else {
+        return (StatusCode::BAD_REQUEST, "Nothing to undo\n").into_response();
+    };
+    if let Err(rejection) = board.authorize_player(&last.team, &headers) {
+        return rejection.into_response();
+    }
+
+    // Like a move, the result comes off the leaderboard before the undo is
+    // saved, and goes back on if saving fails
+    let finished = board.is_finished();
+    if finished {
+        if let Err(rejection) = games.write().await.unrecord(&game).await {
+            return rejection.into_response();
+        }
+    }
+    let mut updated = board.clone();
+    updated.undo();
+    if let Err(rejection) = game.commit(&mut board, updated).await {
+        if finished {
+            let _ = games.write().await.record(&game, &board).await;
+        }
+        return rejection.into_response();
+    }
+    board.render(format)
+}
+
+pub async fn history(game: Game) -> impl IntoResponse {
+    let board = game.board.read().await;
+    let height = board.height();
+
+    Json(json!({
+        "config": board.config,
+        "moves": board.moves.iter().map(|m| m.to_json(height)).collect::<Vec<_>>(),
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReplayMove {
+    team: Cell,
+    #[serde(default)]
+    action: Action,
+    column: usize,
+    row: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Replay {
+    #[serde(default)]
+    config: BoardConfig,
+    moves: Vec<ReplayMove>,
+}
+
+/// Plays a move list, such as one returned by `history`, into a fresh board.
+/// The board is only rendered, not stored as a game.
+pub async fn replay(
+    format: Format,
+    Json(Replay { config, moves }): Json<Replay>,
+) -> impl IntoResponse {
+    if let Err(message) = config.validate() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let mut board = Board::new(config);
+    for (
+        i,
+        ReplayMove {
+            team,
+            action,
+            column,
+            row,
+        },
+    ) in moves.into_iter().enumerate()
+    {
+        let illegal = |reason: &str| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Move {} is illegal: {reason}\n", i + 1),
+            )
+                .into_response()
+        };
+
+        if team == Cell::Empty {
+            return illegal("no team");
+        }
+        if !(1..=board.width()).contains(&column) {
+            return illegal("no such column");
+        }
+        // Moves from `history` say where every piece landed, but only cells
+        // claimed without gravity are up to the player
+        let row = row.filter(|_| config.variant == Variant::NoGravity);
+        if row.is_some_and(|row| !(1..=board.height()).contains(&row)) {
+            return illegal("no such row");
+        }
+        if let Err(reason) = board.config.variant.allows(action, row) {
+            return illegal(reason);
+        }
+        if board.is_finished() {
+            return illegal("the game is over");
+        }
+        if let Err((_, message)) = board.check_turn(&team) {
+            return illegal(message.trim_end());
+        }
+        let row = row.map(|row| board.height() - row);
+        if let Err(reason) = board.apply(team, action, column - 1, row) {
+            return illegal(reason);
         }
-        Status::InProgress => (),
     }
 
-    let team_cell = match team.as_str() {
-        "cookie" => Cell::Cookie,
-        "milk" => Cell::Milk,
-        _ => return StatusCode::BAD_REQUEST.into_response(),
+    board.render(format)
+}
+
+#[derive(serde::Deserialize)]
+pub struct TeamPath {
+    team: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Difficulty {
+    depth: Option<u32>,
+}
+
+impl Difficulty {
+    const DEFAULT_DEPTH: u32 = 5;
+
+    fn depth(&self) -> u32 {
+        self.depth.unwrap_or(Self::DEFAULT_DEPTH)
+    }
+}
+
+/// Runs [`ai::best_move`] on a blocking thread, since searching a big board
+/// takes a while.
+async fn search(board: &Board, team: &Cell, depth: u32) -> Option<ai::Suggestion> {
+    let (board, team) = (board.clone(), team.clone());
+    tokio::task::spawn_blocking(move || ai::best_move(&board, &team, depth))
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Plays the computer's choice of column for `team`. In Pop Out games it
+/// only considers drops.
+pub async fn ai(
+    Path(TeamPath { team }): Path<TeamPath>,
+    Query(difficulty): Query<Difficulty>,
+    game: Game,
+    Extension(games): GamesLock,
+    format: Format,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(team) = Cell::team(&team) else {
+        return StatusCode::BAD_REQUEST.into_response();
     };
 
-    if let Some(row) = board.insert(team_cell, column) {
-        let new_status = board.check(row, column);
-        board.1 = new_status;
-        board.to_string().into_response()
-    } else {
-        (StatusCode::SERVICE_UNAVAILABLE, board.to_string()).into_response()
+    let mut board = game.board.write().await;
+    if let Err(rejection) = board.check_gravity() {
+        return rejection.into_response();
+    }
+    if board.is_finished() {
+        return (StatusCode::SERVICE_UNAVAILABLE, board.render(format)).into_response();
+    }
+    if let Err(rejection) = board.authorize(&team, &headers) {
+        return rejection.into_response();
+    }
+
+    // The board stays locked so the move is made on the position searched
+    let Some(suggestion) = search(&board, &team, difficulty.depth()).await else {
+        return (StatusCode::SERVICE_UNAVAILABLE, board.render(format)).into_response();
+    };
+    let mut updated = board.clone();
+    if updated
+        .apply(team, Action::Drop, suggestion.column, None)
+        .is_err()
+    {
+        return (StatusCode::SERVICE_UNAVAILABLE, board.render(format)).into_response();
     }
+    if let Err(rejection) = game.play(&games, &mut board, updated).await {
+        return rejection.into_response();
+    }
+    board.render(format)
 }
 
-pub async fn random_board(Extension(random_nums): RandLock) -> String {
-    let mut random_nums = random_nums.write().await;
+#[derive(serde::Deserialize)]
+pub struct AnalysisDepth {
+    depth: Option<u32>,
+}
 
-    let mut board = Board::default();
-    let mut winner = Cell::Empty;
-    for i in 0..4 {
-        for j in 0..4 {
-            board.0[i][j] = if random_nums.gen::<bool>() {
-                Cell::Cookie
-            } else {
-                Cell::Milk
-            };
-            match board.check(i, j) {
-                Status::InProgress => (),
-                Status::Winner(curr_winner) => {
-                    winner = curr_winner;
-                }
+pub async fn stats(Extension(games): GamesLock) -> impl IntoResponse {
+    Json(games.read().await.summary.to_json())
+}
+
+/// Reports the lines, threats, forced wins and full columns of the position.
+/// `depth` bounds the forced-win search, in moves of the winning team.
+pub async fn analysis(
+    Query(AnalysisDepth { depth }): Query<AnalysisDepth>,
+    game: Game,
+) -> impl IntoResponse {
+    let board = game.board.read().await.clone();
+    if let Err(rejection) = board.check_gravity() {
+        return rejection.into_response();
+    }
+    // Searching for forced wins takes a while on big boards
+    let depth = depth.unwrap_or(2);
+    match tokio::task::spawn_blocking(move || board.analysis(depth)).await {
+        Ok(analysis) => Json(analysis).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Suggests a column for `team` along with its evaluation, without playing it.
+pub async fn hint(
+    Path(TeamPath { team }): Path<TeamPath>,
+    Query(difficulty): Query<Difficulty>,
+    game: Game,
+    format: Format,
+) -> impl IntoResponse {
+    let Some(team) = Cell::team(&team) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let board = game.board.read().await.clone();
+    if let Err(rejection) = board.check_gravity() {
+        return rejection.into_response();
+    }
+    if board.is_finished() {
+        return (StatusCode::SERVICE_UNAVAILABLE, board.render(format)).into_response();
+    }
+
+    match search(&board, &team, difficulty.depth()).await {
+        Some(ai::Suggestion { column, score }) => {
+            Json(json!({ "column": column + 1, "score": score })).into_response()
+        }
+        None => (StatusCode::SERVICE_UNAVAILABLE, board.render(format)).into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct RandomBoards {
+    /// Seeds a private RNG instead of drawing from the shared one.
+    seed: Option<u64>,
+    /// Returns this many boards as a list, rather than a single board.
+    count: Option<usize>,
+}
+
+impl RandomBoards {
+    const MAX_COUNT: usize = 100;
+}
+
+pub async fn random_board(
+    Query(RandomBoards { seed, count }): Query<RandomBoards>,
+    format: Format,
+    Extension(random_nums): RandLock,
+) -> Response {
+    let n = count.unwrap_or(1);
+    if !(1..=RandomBoards::MAX_COUNT).contains(&n) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Count must be between 1 and {}\n", RandomBoards::MAX_COUNT),
+        )
+            .into_response();
+    }
+
+    let boards = match seed {
+        Some(seed) => {
+            let mut random_nums = rand::rngs::StdRng::seed_from_u64(seed);
+            (0..n)
+                .map(|_| Board::random(&mut random_nums))
+                .collect_vec()
+        }
+        None => {
+            let mut random_nums = random_nums.write().await;
+            (0..n)
+                .map(|_| Board::random(&mut *random_nums))
+                .collect_vec()
+        }
+    };
+
+    match (count, format) {
+        (None, _) => boards.first().map_or_else(
+            || StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            |board| board.render(format),
+        ),
+        (Some(_), Format::Text) => boards.iter().join("\n").into_response(),
+        (Some(_), Format::Json) => {
+            Json(boards.iter().map(Board::to_json).collect_vec()).into_response()
+        }
+        (Some(_), Format::Svg) => (
+            StatusCode::NOT_ACCEPTABLE,
+            "SVG renders one board at a time\n",
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{router, test_utils::collect_body};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use http_body_util::BodyExt as _;
+    use tower::ServiceExt as _;
+
+    async fn request(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+
+        let status = response.status();
+        (status, collect_body(response).await)
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str) -> (StatusCode, String) {
+        request(
+            app,
+            Request::builder()
+                .uri(uri)
+                .method(method)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+    }
+
+    async fn create_game_with(app: &Router, config: &'static str) -> String {
+        let (status, body) = request(
+            app,
+            Request::builder()
+                .uri("/12/games")
+                .method("POST")
+                .body(Body::from(config))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    async fn create_game(app: &Router) -> String {
+        create_game_with(app, "").await
+    }
+
+    #[tokio::test]
+    async fn games_are_independent() {
+        let app = router();
+        let id = create_game(&app).await;
+
+        let (status, _) = send(&app, "POST", &format!("/12/games/{id}/place/cookie/1")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send(&app, "GET", &format!("/12/games/{id}/board")).await;
+        assert_eq!(
+            body,
+            "⬜⬛⬛⬛⬛⬜
+⬜⬛⬛⬛⬛⬜
+⬜⬛⬛⬛⬛⬜
+⬜🍪⬛⬛⬛⬜
+⬜⬜⬜⬜⬜⬜
+"
+        );
+
+        let (_, body) = send(&app, "GET", "/12/board").await;
+        assert_eq!(
+            body,
+            "⬜⬛⬛⬛⬛⬜
+⬜⬛⬛⬛⬛⬜
+⬜⬛⬛⬛⬛⬜
+⬜⬛⬛⬛⬛⬜
+⬜⬜⬜⬜⬜⬜
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_three() {
+        let app = router();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/12/games")
+                    .method("POST")
+                    .body(Body::from(r#"{"width": 5, "height": 3, "connect": 3}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body =
+            serde_json::from_str::<serde_json::Value>(&collect_body(response).await).unwrap();
+        let id = body["id"].as_str().unwrap();
+
+        for (team, column) in [
+            ("cookie", 3),
+            ("milk", 4),
+            ("cookie", 4),
+            ("milk", 5),
+            ("milk", 5),
+        ] {
+            let (status, _) = send(
+                &app,
+                "POST",
+                &format!("/12/games/{id}/place/{team}/{column}"),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let (status, body) = send(&app, "POST", &format!("/12/games/{id}/place/cookie/5")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            "⬜⬛⬛⬛⬛🍪⬜
+⬜⬛⬛⬛🍪🥛⬜
+⬜⬛⬛🍪🥛🥛⬜
+⬜⬜⬜⬜⬜⬜⬜
+🍪 wins!
+"
+        );
+
+        let (status, _) = send(&app, "POST", &format!("/12/games/{id}/place/milk/6")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn hint_and_ai() {
+        let app = router();
+        for (team, column) in [
+            ("cookie", 1),
+            ("milk", 4),
+            ("cookie", 1),
+            ("milk", 4),
+            ("cookie", 1),
+        ] {
+            send(&app, "POST", &format!("/12/place/{team}/{column}")).await;
+        }
+
+        let (status, body) = send(&app, "GET", "/12/hint/milk?depth=3").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["column"], 1);
+
+        let (status, body) = send(&app, "POST", "/12/ai/cookie").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.ends_with("🍪 wins!\n"));
+
+        let (status, body) = send(&app, "GET", "/12/hint/milk?format=json").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["winner"], "cookie");
+
+        let id = create_game(&app).await;
+        let (status, body) = send(&app, "GET", &format!("/12/games/{id}/hint/milk")).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert!(matches!(body["column"].as_u64(), Some(1..=4)));
+    }
+
+    #[tokio::test]
+    async fn strict_turns_and_tokens() {
+        let app = router();
+        let id = create_game_with(&app, r#"{"strict": true}"#).await;
+
+        let (status, body) = send(&app, "POST", &format!("/12/games/{id}/join/milk")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let (status, _) = send(&app, "POST", &format!("/12/games/{id}/join/milk")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&app, "POST", "/12/join/milk").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send(&app, "POST", &format!("/12/games/{id}/place/cookie/1")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&app, "POST", &format!("/12/games/{id}/place/cookie/2")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body, "It is 🥛's turn\n");
+
+        let (status, _) = send(&app, "POST", &format!("/12/games/{id}/place/milk/2")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let place_milk = |token: String| {
+            Request::builder()
+                .uri(format!("/12/games/{id}/place/milk/2"))
+                .method("POST")
+                .header("Authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, _) = request(&app, place_milk(id.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = request(&app, place_milk(token.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let reset = |token: Option<&str>| {
+            let mut request = Request::builder()
+                .uri(format!("/12/games/{id}/reset"))
+                .method("POST");
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {token}"));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let (status, _) = request(&app, reset(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = request(&app, reset(Some(&id))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let import = |token: Option<&str>| {
+            let mut request = Request::builder()
+                .uri(format!("/12/games/{id}/board/import"))
+                .method("POST");
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {token}"));
             }
+            request.body(Body::from("..../..../..../c...")).unwrap()
+        };
+        let (status, _) = request(&app, import(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = request(&app, import(Some(&id))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = request(&app, import(Some(&token))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = request(&app, reset(Some(&token))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains('🍪'));
+    }
+
+    #[tokio::test]
+    async fn history_undo_and_replay() {
+        let app = router();
+        for (team, column) in [("cookie", 1), ("cookie", 1), ("cookie", 1), ("milk", 2)] {
+            send(&app, "POST", &format!("/12/place/{team}/{column}")).await;
         }
+        send(&app, "POST", "/12/place/cookie/1").await;
+
+        let (status, body) = send(&app, "POST", "/12/undo").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            "⬜⬛⬛⬛⬛⬜
+⬜🍪⬛⬛⬛⬜
+⬜🍪⬛⬛⬛⬜
+⬜🍪🥛⬛⬛⬜
+⬜⬜⬜⬜⬜⬜
+"
+        );
+
+        let (status, history) = send(&app, "GET", "/12/history").await;
+        assert_eq!(status, StatusCode::OK);
+        let parsed = serde_json::from_str::<serde_json::Value>(&history).unwrap();
+        assert_eq!(parsed["moves"].as_array().unwrap().len(), 4);
+        assert_eq!(parsed["moves"][2]["row"], 3);
+        assert_eq!(parsed["moves"][3]["team"], "milk");
+
+        let (status, body) = request(
+            &app,
+            Request::builder()
+                .uri("/12/replay")
+                .method("POST")
+                .header("Content-Type", "application/json")
+                .body(Body::from(history))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, board) = send(&app, "GET", "/12/board").await;
+        assert_eq!(body, board);
+
+        send(&app, "POST", "/12/reset").await;
+        let (status, _) = send(&app, "POST", "/12/undo").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn pop_out() {
+        let app = router();
+        let id = create_game_with(&app, r#"{"connect": 3, "variant": "pop_out"}"#).await;
+        let uri = |path: &str| format!("/12/games/{id}/{path}");
+        for (team, column) in [("milk", 1), ("cookie", 1), ("cookie", 1), ("cookie", 2)] {
+            send(&app, "POST", &uri(&format!("place/{team}/{column}"))).await;
+        }
+        send(&app, "POST", &uri("place/cookie/3")).await;
+
+        let (status, _) = send(&app, "POST", &uri("place/cookie/1?action=pop")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, body) = send(&app, "POST", &uri("place/milk/1?action=pop")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            "⬜⬛⬛⬛⬛⬜
+⬜⬛⬛⬛⬛⬜
+⬜🍪⬛⬛⬛⬜
+⬜🍪🍪🍪⬛⬜
+⬜⬜⬜⬜⬜⬜
+🍪 wins!
+"
+        );
+
+        let (_, body) = send(&app, "POST", &uri("undo")).await;
+        assert!(body.contains("⬜🥛🍪🍪⬛⬜\n⬜⬜⬜⬜⬜⬜\n"));
+        assert!(!body.contains("wins"));
+
+        let (status, _) = send(&app, "POST", "/12/place/milk/1?action=pop").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // A full board where 🥛 has nothing to pop leaves them stuck
+        let config = r#"{"width": 2, "height": 3, "connect": 3, "variant": "pop_out"}"#;
+        let id = create_game_with(&app, config).await;
+        let uri = |path: &str| format!("/12/games/{id}/{path}");
+        for (team, column) in [("cookie", 1), ("cookie", 2), ("milk", 1), ("milk", 2)] {
+            send(&app, "POST", &uri(&format!("place/{team}/{column}"))).await;
+        }
+        let (_, body) = send(&app, "POST", &uri("place/milk/1")).await;
+        assert!(!body.contains("No winner"));
+        let (_, body) = send(&app, "POST", &uri("place/cookie/2")).await;
+        assert!(body.ends_with("No winner.\n"));
+    }
+
+    #[tokio::test]
+    async fn no_gravity() {
+        let app = router();
+        let id = create_game_with(&app, r#"{"connect": 3, "variant": "no_gravity"}"#).await;
+        let uri = |path: &str| format!("/12/games/{id}/{path}");
+
+        let (status, _) = send(&app, "POST", &uri("place/cookie/1")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        send(&app, "POST", &uri("place/cookie/1?row=4")).await;
+        let (status, _) = send(&app, "POST", &uri("place/milk/1?row=4")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, _) = send(&app, "POST", &uri("ai/milk")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        send(&app, "POST", &uri("place/cookie/2?row=3")).await;
+        let (status, body) = send(&app, "POST", &uri("place/cookie/3?row=2")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            "⬜🍪⬛⬛⬛⬜
+⬜⬛🍪⬛⬛⬜
+⬜⬛⬛🍪⬛⬜
+⬜⬛⬛⬛⬛⬜
+⬜⬜⬜⬜⬜⬜
+🍪 wins!
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn json_board() {
+        let app = router();
+        send(&app, "POST", "/12/place/milk/2").await;
+
+        let (status, body) = request(
+            &app,
+            Request::builder()
+                .uri("/12/place/cookie/2")
+                .method("POST")
+                .header("Accept", "application/json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "grid": [
+                    ["empty", "empty", "empty", "empty"],
+                    ["empty", "empty", "empty", "empty"],
+                    ["empty", "cookie", "empty", "empty"],
+                    ["empty", "milk", "empty", "empty"],
+                ],
+                "status": "in_progress",
+                "winner": null,
+                "next": "milk",
+                "moves": 2,
+            })
+        );
+
+        let (_, body) = send(&app, "GET", "/12/board").await;
+        assert!(body.starts_with("⬜⬛⬛⬛⬛⬜"));
+    }
+
+    #[tokio::test]
+    async fn svg_board() {
+        let app = router();
+        for _ in 0..3 {
+            send(&app, "POST", "/12/place/cookie/1").await;
+            send(&app, "POST", "/12/place/milk/2").await;
+        }
+        send(&app, "POST", "/12/place/cookie/1").await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/12/board")
+                    .header("Accept", "image/svg+xml")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/svg+xml");
+        let body = collect_body(response).await;
+        assert!(body.starts_with("<svg "));
+        assert_eq!(body.matches("<circle ").count(), 7);
+        assert!(body.contains(r#"<line x1="60" y1="20" x2="60" y2="140""#));
+        assert!(body.contains(">Cookie wins!</text>"));
+
+        let (_, body) = send(&app, "GET", "/12/board?format=svg").await;
+        assert!(body.trim_end().ends_with("</svg>"));
+        let (status, _) = send(&app, "GET", "/12/random-board?count=2&format=svg").await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn board_stream() {
+        let app = router();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/12/board/stream?format=json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body();
+
+        async fn next_event(body: &mut Body) -> String {
+            let frame = body.frame().await.unwrap().unwrap();
+            String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap()
+        }
+
+        assert!(next_event(&mut body).await.contains(r#""moves":0"#));
+        send(&app, "POST", "/12/place/cookie/1").await;
+        assert!(next_event(&mut body).await.contains(r#""moves":1"#));
+        send(&app, "POST", "/12/reset").await;
+        assert!(next_event(&mut body).await.contains(r#""moves":0"#));
+    }
+
+    #[tokio::test]
+    async fn seeded_random_boards() {
+        let app = router();
+
+        let (_, first) = send(&app, "GET", "/12/random-board").await;
+        let (_, second) = send(&app, "GET", "/12/random-board").await;
+
+        let (status, seeded) = send(&app, "GET", "/12/random-board?seed=2024&count=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(seeded, format!("{first}\n{second}"));
+
+        let (_, third) = send(&app, "GET", "/12/random-board").await;
+        send(&app, "POST", "/12/reset").await;
+        send(&app, "GET", "/12/random-board").await;
+        send(&app, "GET", "/12/random-board").await;
+        let (_, after_reset) = send(&app, "GET", "/12/random-board").await;
+        assert_eq!(third, after_reset);
+
+        let (status, body) = send(&app, "GET", "/12/random-board?seed=7&count=3&format=json").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 3);
+
+        let (status, _) = send(&app, "GET", "/12/random-board?count=0").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
     }
 
-    board.1 = Status::Winner(winner);
-    board.to_string()
+    #[tokio::test]
+    async fn import_position() {
+        let app = router();
+        let import = |position: &str| {
+            Request::builder()
+                .uri("/12/board/import")
+                .method("POST")
+                .body(Body::from(position.to_string()))
+                .unwrap()
+        };
+
+        let (status, body) = request(&app, import("..../c.../cm../cmm.")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            "⬜⬛⬛⬛⬛⬜
+⬜🍪⬛⬛⬛⬜
+⬜🍪🥛⬛⬛⬜
+⬜🍪🥛🥛⬛⬜
+⬜⬜⬜⬜⬜⬜
+"
+        );
+
+        let (status, body) = send(&app, "POST", "/12/place/cookie/1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.ends_with("🍪 wins!\n"));
+
+        let (status, body) = request(&app, import(&body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.ends_with("🍪 wins!\n"));
+
+        let (status, _) = request(&app, import("c.../.m..")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = request(&app, import("..../cc..")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = request(&app, import("..../cm.")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn position_analysis() {
+        let app = router();
+        let (status, _) = request(
+            &app,
+            Request::builder()
+                .uri("/12/board/import")
+                .method("POST")
+                .body(Body::from("..../..cm/.cmc/cmmm"))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&app, "GET", "/12/analysis").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["lines"], serde_json::json!([]));
+        assert_eq!(body["threats"]["cookie"], serde_json::json!([4]));
+        assert_eq!(body["threats"]["milk"], serde_json::json!([]));
+        assert_eq!(body["forced_win"]["cookie"], true);
+        assert_eq!(body["depth"], 2);
+        assert_eq!(body["full_columns"], serde_json::json!([]));
+
+        send(&app, "POST", "/12/place/cookie/4").await;
+        let (_, body) = send(&app, "GET", "/12/analysis").await;
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(
+            body["lines"],
+            serde_json::json!([{
+                "team": "cookie",
+                "direction": "anti_diagonal",
+                "from": { "column": 4, "row": 4 },
+                "to": { "column": 1, "row": 1 },
+                "length": 4,
+            }])
+        );
+        assert_eq!(body["full_columns"], serde_json::json!([4]));
+
+        // Big boards run out of budget before the depth asked for
+        let (status, body) = request(
+            &app,
+            Request::builder()
+                .uri("/12/games")
+                .method("POST")
+                .body(Body::from(r#"{"width": 32, "height": 32, "connect": 5}"#))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        let id = body["id"].as_str().unwrap();
+        let uri = format!("/12/games/{id}/analysis?depth=4");
+        let (status, body) = send(&app, "GET", &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["forced_win"]["cookie"], false);
+        assert!(body["depth"].as_u64().unwrap() < 4);
+    }
+
+    #[tokio::test]
+    async fn leaderboard() {
+        async fn join(app: &Router, id: &str, team: &str, name: &str) -> String {
+            let uri = format!("/12/games/{id}/join/{team}?name={name}");
+            let (_, body) = send(app, "POST", &uri).await;
+            let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+            body["token"].as_str().unwrap().to_string()
+        }
+        fn place(id: &str, token: &str, team: &str, column: usize) -> Request<Body> {
+            Request::builder()
+                .uri(format!("/12/games/{id}/place/{team}/{column}"))
+                .method("POST")
+                .header("Authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        }
+
+        let app = router();
+        for finished in 0..2 {
+            let id = create_game(&app).await;
+            let alice = join(&app, &id, "cookie", "alice").await;
+            let bob = join(&app, &id, "milk", "bob").await;
+            for _ in 0..3 {
+                request(&app, place(&id, &alice, "cookie", 1)).await;
+                request(&app, place(&id, &bob, "milk", 2)).await;
+            }
+            let (_, body) = request(&app, place(&id, &alice, "cookie", 1)).await;
+            assert!(body.ends_with("🍪 wins!\n"));
+
+            // Taking the winning move back and making it again counts once
+            let undo = Request::builder()
+                .uri(format!("/12/games/{id}/undo"))
+                .method("POST")
+                .header("Authorization", format!("Bearer {alice}"))
+                .body(Body::empty())
+                .unwrap();
+            let (status, _) = request(&app, undo).await;
+            assert_eq!(status, StatusCode::OK);
+            let (_, body) = send(&app, "GET", "/12/stats").await;
+            let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+            assert_eq!(body["games"], finished);
+            let (_, body) = request(&app, place(&id, &alice, "cookie", 1)).await;
+            assert!(body.ends_with("🍪 wins!\n"));
+        }
+
+        let (status, body) = send(&app, "GET", "/12/stats").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "games": 2,
+                "teams": {
+                    "cookie": { "wins": 2, "losses": 0, "draws": 0 },
+                    "milk": { "wins": 0, "losses": 2, "draws": 0 },
+                },
+                "players": [
+                    { "name": "alice", "record": { "wins": 2, "losses": 0, "draws": 0 } },
+                    { "name": "bob", "record": { "wins": 0, "losses": 2, "draws": 0 } },
+                ],
+                "average_moves": 7.0,
+                "lines": { "vertical": 2 },
+                "most_common_line": "vertical",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_game() {
+        let app = router();
+
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/12/games/00000000-0000-0000-0000-000000000000/board",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, "GET", "/12/games/not-a-game/board").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
 }
@@ -0,0 +1,244 @@
+use itertools::Itertools;
+
+use super::{Board, Cell, Direction, Status};
+
+/// Score of a won position, before the bonus for winning sooner.
+const WIN: i32 = 1_000_000;
+
+/// The deepest search a client may ask for. Searches stop short of it once
+/// they run through [`BUDGET`].
+pub const MAX_DEPTH: u32 = 8;
+
+/// Work the static evaluation may do in one search, counted as cells times
+/// the line length. Each ply multiplies the work by up to the board width,
+/// and each evaluation takes longer the bigger the board, so big boards get
+/// searched less deeply. A few hundred milliseconds' worth.
+const BUDGET: u64 = 10_000_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Suggestion {
+    /// Zero-based column to play.
+    pub column: usize,
+    /// Evaluation of the position after playing `column`, from the point of
+    /// view of the team moving. Positive is good for that team.
+    pub score: i32,
+}
+
+/// Finds the best column for `team` with an alpha-beta search up to `depth`
+/// plies deep, or as deep as the [`BUDGET`] allows. Returns `None` when every
+/// column is full.
+pub fn best_move(board: &Board, team: &Cell, depth: u32) -> Option<Suggestion> {
+    best_move_within(board, team, depth, BUDGET)
+}
+
+fn best_move_within(board: &Board, team: &Cell, depth: u32, budget: u64) -> Option<Suggestion> {
+    let mut board = board.clone();
+    let mut search = Search { budget };
+
+    // Deepen one ply at a time, keeping the last search that finished, or
+    // the first even if it didn't. A single ply costs a few evaluations per
+    // column, far below the budget, so it normally does.
+    let mut best = None;
+    for depth in 1..=depth.clamp(1, MAX_DEPTH) {
+        let suggestion = search.best_move(&mut board, team, depth);
+        if search.exhausted() && best.is_some() {
+            break;
+        }
+        best = suggestion;
+    }
+
+    best
+}
+
+/// Columns from the centre outwards, since central moves are usually
+/// strongest and trying them first lets alpha-beta prune more.
+fn search_order(width: usize) -> impl Iterator<Item = usize> {
+    (0..width).sorted_by_key(move |column| (2 * column).abs_diff(width.saturating_sub(1)))
+}
+
+struct Search {
+    /// Work the static evaluation may still do.
+    budget: u64,
+}
+
+impl Search {
+    fn exhausted(&self) -> bool {
+        self.budget == 0
+    }
+
+    fn best_move(&mut self, board: &mut Board, team: &Cell, depth: u32) -> Option<Suggestion> {
+        let mut best: Option<Suggestion> = None;
+        for column in search_order(board.width()) {
+            let alpha = best.map_or(-WIN * 2, |best| best.score);
+            let Some(score) = self.score_move(board, team, column, depth, alpha, WIN * 2) else {
+                continue;
+            };
+            if best.is_none_or(|best| score > best.score) {
+                best = Some(Suggestion { column, score });
+            }
+        }
+
+        best
+    }
+
+    /// Plays `team` into `column`, scores the result and takes the piece
+    /// back. Returns `None` if the column is full.
+    fn score_move(
+        &mut self,
+        board: &mut Board,
+        team: &Cell,
+        column: usize,
+        depth: u32,
+        alpha: i32,
+        beta: i32,
+    ) -> Option<i32> {
+        let row = board.insert(team.clone(), column)?;
+
+        let score = match board.check(row, column) {
+            Status::Winner(Cell::Empty) => 0,
+            Status::Winner(_) => WIN + depth as i32,
+            Status::InProgress if depth <= 1 => self.evaluate(board, team),
+            Status::InProgress => -self.negamax(board, &team.opponent(), depth - 1, -beta, -alpha),
+        };
+
+        board.grid[row][column] = Cell::Empty;
+        Some(score)
+    }
+
+    fn negamax(
+        &mut self,
+        board: &mut Board,
+        team: &Cell,
+        depth: u32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        let mut best = None;
+        for column in search_order(board.width()) {
+            let Some(score) = self.score_move(board, team, column, depth, alpha, beta) else {
+                continue;
+            };
+
+            best = Some(best.map_or(score, |best: i32| best.max(score)));
+            alpha = alpha.max(score);
+            // Once the budget runs out the scores are meaningless anyway
+            if alpha >= beta || self.exhausted() {
+                break;
+            }
+        }
+
+        // No legal moves left means the board filled up without a winner
+        best.unwrap_or(0)
+    }
+
+    fn evaluate(&mut self, board: &Board, team: &Cell) -> i32 {
+        let work = (board.width() * board.height() * board.config.connect) as u64;
+        if self.budget < work {
+            self.budget = 0;
+            return 0;
+        }
+        self.budget -= work;
+        evaluate(board, team)
+    }
+}
+
+/// Static evaluation of a position for `team`: every window of `connect`
+/// cells that only one team occupies counts for that team, weighted by how
+/// many pieces it already holds.
+fn evaluate(board: &Board, team: &Cell) -> i32 {
+    let connect = board.config.connect as isize;
+    let opponent = team.opponent();
+
+    let mut score = 0;
+    for row in 0..board.height() as isize {
+        for col in 0..board.width() as isize {
+            for (d_row, d_col) in Direction::ALL.map(Direction::delta) {
+                let window = (0..connect)
+                    .map(|step| {
+                        let row = usize::try_from(row + step * d_row).ok()?;
+                        let col = usize::try_from(col + step * d_col).ok()?;
+                        board.grid.get(row)?.get(col)
+                    })
+                    .collect::<Option<Vec<_>>>();
+                let Some(window) = window else {
+                    continue;
+                };
+
+                let ours = window.iter().filter(|cell| **cell == team).count() as i32;
+                let theirs = window.iter().filter(|cell| **cell == &opponent).count() as i32;
+                match (ours, theirs) {
+                    (0, 0) => (),
+                    (ours, 0) => score += ours * ours,
+                    (0, theirs) => score -= theirs * theirs,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{best_move, best_move_within};
+    use crate::solutions::day12::{Board, BoardConfig, Cell};
+
+    fn board(moves: &[(Cell, usize)]) -> Board {
+        let mut board = Board::new(BoardConfig {
+            width: 7,
+            height: 6,
+            connect: 4,
+            ..Default::default()
+        });
+        for (team, column) in moves {
+            board.insert(team.clone(), *column).unwrap();
+        }
+        board
+    }
+
+    #[test]
+    fn takes_the_win() {
+        let board = board(&[
+            (Cell::Cookie, 0),
+            (Cell::Milk, 6),
+            (Cell::Cookie, 1),
+            (Cell::Milk, 6),
+            (Cell::Cookie, 2),
+            (Cell::Milk, 6),
+        ]);
+
+        let suggestion = best_move(&board, &Cell::Cookie, 4).unwrap();
+        assert_eq!(suggestion.column, 3);
+        assert!(suggestion.score > 0);
+    }
+
+    #[test]
+    fn blocks_the_loss() {
+        let board = board(&[
+            (Cell::Milk, 6),
+            (Cell::Cookie, 0),
+            (Cell::Milk, 6),
+            (Cell::Cookie, 1),
+            (Cell::Milk, 6),
+        ]);
+
+        assert_eq!(best_move(&board, &Cell::Cookie, 4).unwrap().column, 6);
+    }
+
+    #[test]
+    fn stops_at_the_budget() {
+        let board = board(&[
+            (Cell::Cookie, 0),
+            (Cell::Milk, 6),
+            (Cell::Cookie, 1),
+            (Cell::Milk, 6),
+            (Cell::Cookie, 2),
+            (Cell::Milk, 6),
+        ]);
+
+        // Enough for a ply, but nowhere near eight
+        let suggestion = best_move_within(&board, &Cell::Cookie, 8, 10_000).unwrap();
+        assert_eq!(suggestion.column, 3);
+    }
+}
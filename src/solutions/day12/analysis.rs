@@ -0,0 +1,244 @@
+use itertools::Itertools;
+use serde_json::json;
+
+use super::{Board, Cell, Status};
+
+/// The deepest forced-win search a client may ask for, counted in the
+/// attacking team's own moves.
+pub const MAX_DEPTH: u32 = 4;
+
+/// Positions the forced-win search may look at for each team. Searches stop
+/// short of the depth asked for once they run through it, which keeps big
+/// boards to a few hundred milliseconds.
+const BUDGET: u64 = 2_000_000;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Horizontal,
+    Vertical,
+    /// Top left to bottom right.
+    Diagonal,
+    /// Top right to bottom left.
+    AntiDiagonal,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Horizontal,
+        Direction::Vertical,
+        Direction::Diagonal,
+        Direction::AntiDiagonal,
+    ];
+
+    /// Step in `(row, column)` grid indices.
+    pub fn delta(self) -> (isize, isize) {
+        match self {
+            Direction::Horizontal => (0, 1),
+            Direction::Vertical => (1, 0),
+            Direction::Diagonal => (1, 1),
+            Direction::AntiDiagonal => (1, -1),
+        }
+    }
+}
+
+/// A run of at least `connect` pieces of one team, from its first cell in
+/// `direction` to its last. Cells are zero-based grid indices.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub team: Cell,
+    pub direction: Direction,
+    pub start: (usize, usize),
+    pub length: usize,
+}
+
+impl Line {
+    /// The last cell of the line.
+    pub fn end(&self) -> (usize, usize) {
+        let (d_row, d_col) = self.direction.delta();
+        let steps = self.length as isize - 1;
+        let (row, col) = self.start;
+        (
+            row.saturating_add_signed(d_row * steps),
+            col.saturating_add_signed(d_col * steps),
+        )
+    }
+
+    fn to_json(&self, height: usize) -> serde_json::Value {
+        let cell = |(row, col): (usize, usize)| json!({ "column": col + 1, "row": height - row });
+
+        json!({
+            "team": self.team,
+            "direction": self.direction,
+            "from": cell(self.start),
+            "to": cell(self.end()),
+            "length": self.length,
+        })
+    }
+}
+
+impl Board {
+    /// Every complete line on the board, each maximal run reported once.
+    pub fn lines(&self) -> Vec<Line> {
+        (0..self.height())
+            .cartesian_product(0..self.width())
+            .cartesian_product(Direction::ALL)
+            .filter_map(|((row, col), direction)| {
+                let team = &self.grid[row][col];
+                let (d_row, d_col) = direction.delta();
+                let previous = row
+                    .checked_add_signed(-d_row)
+                    .zip(col.checked_add_signed(-d_col))
+                    .and_then(|(row, col)| self.grid.get(row)?.get(col));
+                if *team == Cell::Empty || previous == Some(team) {
+                    return None;
+                }
+
+                let length = self.run_length(row, col, (d_row, d_col));
+                (length >= self.config.connect).then(|| Line {
+                    team: team.clone(),
+                    direction,
+                    start: (row, col),
+                    length,
+                })
+            })
+            .collect()
+    }
+
+    /// Zero-based columns where `team` would complete a line right away.
+    fn winning_columns(&mut self, team: &Cell) -> Vec<usize> {
+        (0..self.width())
+            .filter(|&col| {
+                let Some(row) = self.insert(team.clone(), col) else {
+                    return false;
+                };
+                let wins =
+                    matches!(self.check(row, col), Status::Winner(ref winner) if winner == team);
+                self.grid[row][col] = Cell::Empty;
+                wins
+            })
+            .collect()
+    }
+
+    /// Whether `team`, moving now, can win within `moves` of its own moves
+    /// whatever the opponent replies, or `None` if `budget` runs out before
+    /// that's known. Each position looked at costs one.
+    fn forced_win(&mut self, team: &Cell, moves: u32, budget: &mut u64) -> Option<bool> {
+        if moves == 0 {
+            return Some(false);
+        }
+
+        for col in 0..self.width() {
+            let Some(row) = self.insert(team.clone(), col) else {
+                continue;
+            };
+            let forced = match budget.checked_sub(1) {
+                None => None,
+                Some(left) => {
+                    *budget = left;
+                    match self.check(row, col) {
+                        Status::Winner(ref winner) if winner == team => Some(true),
+                        Status::Winner(_) => Some(false),
+                        Status::InProgress => self.forced_after_any_reply(team, moves - 1, budget),
+                    }
+                }
+            };
+            self.grid[row][col] = Cell::Empty;
+            if forced? {
+                return Some(true);
+            }
+        }
+        Some(false)
+    }
+
+    fn forced_after_any_reply(
+        &mut self,
+        team: &Cell,
+        moves: u32,
+        budget: &mut u64,
+    ) -> Option<bool> {
+        let opponent = team.opponent();
+        let mut replied = false;
+
+        for col in 0..self.width() {
+            let Some(row) = self.insert(opponent.clone(), col) else {
+                continue;
+            };
+            replied = true;
+            let forced = match budget.checked_sub(1) {
+                None => None,
+                Some(left) => {
+                    *budget = left;
+                    match self.check(row, col) {
+                        Status::InProgress => self.forced_win(team, moves, budget),
+                        Status::Winner(_) => Some(false),
+                    }
+                }
+            };
+            self.grid[row][col] = Cell::Empty;
+            if !forced? {
+                return Some(false);
+            }
+        }
+
+        // A full board is a draw, not a win
+        Some(replied)
+    }
+
+    /// Whether `team` has a forced win within `depth` of its own moves,
+    /// searching one move deeper at a time until [`BUDGET`] runs out, and how
+    /// deep it got. A forced win found is one at any greater depth too.
+    fn forced_win_within(&mut self, team: &Cell, depth: u32) -> (bool, u32) {
+        let mut budget = BUDGET;
+        let mut searched = 0;
+        for moves in 1..=depth {
+            match self.forced_win(team, moves, &mut budget) {
+                Some(true) => return (true, depth),
+                Some(false) => searched = moves,
+                None => break,
+            }
+        }
+        (false, searched)
+    }
+
+    /// Lines, threats, forced wins within `depth` moves and full columns of
+    /// the position. Threats and forced wins are given for each team as if
+    /// it were that team's turn. The `depth` reported is how deep both
+    /// searches got, which on big boards can be less than asked for.
+    pub fn analysis(&self, depth: u32) -> serde_json::Value {
+        let mut board = self.clone();
+        let mut depth = depth.min(MAX_DEPTH);
+        let in_progress = matches!(self.status, Status::InProgress);
+
+        let mut threats = serde_json::Map::new();
+        let mut forced_wins = serde_json::Map::new();
+        for team in [Cell::Cookie, Cell::Milk] {
+            let (columns, forced) = if in_progress {
+                let columns = board.winning_columns(&team);
+                let (forced, searched) = board.forced_win_within(&team, depth);
+                depth = depth.min(searched);
+                (columns, forced)
+            } else {
+                (Vec::new(), false)
+            };
+            let columns = columns.iter().map(|col| col + 1).collect_vec();
+            threats.insert(team.name().to_string(), json!(columns));
+            forced_wins.insert(team.name().to_string(), json!(forced));
+        }
+
+        let full_columns = (0..self.width())
+            .filter(|&col| self.grid.first().is_some_and(|top| top[col] != Cell::Empty))
+            .map(|col| col + 1)
+            .collect_vec();
+
+        json!({
+            "lines": self.lines().iter().map(|line| line.to_json(self.height())).collect_vec(),
+            "threats": threats,
+            "forced_win": forced_wins,
+            "depth": depth,
+            "full_columns": full_columns,
+        })
+    }
+}
@@ -0,0 +1,59 @@
+//! Reading positions back in, either as the emoji text `Board` displays or
+//! in a compact notation with one character per cell: `c` for cookie, `m` for
+//! milk and `.` for empty, with rows separated by newlines or `/`.
+
+use super::Cell;
+
+const FRAME: char = '⬜';
+
+/// Parses a position into its grid of cells, top row first. Frame rows and
+/// the winner line of the emoji rendering are skipped, since the status is
+/// recomputed from the pieces anyway.
+pub fn parse(text: &str) -> Result<Vec<Vec<Cell>>, String> {
+    text.split(['\n', '/'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter(|line| !line.chars().all(|c| c == FRAME))
+        .filter(|line| !line.ends_with("wins!") && *line != "No winner.")
+        .enumerate()
+        .map(|(i, line)| {
+            line.trim_matches(FRAME)
+                .chars()
+                .map(|c| match c {
+                    '⬛' | '.' => Ok(Cell::Empty),
+                    '🍪' | 'c' | 'C' => Ok(Cell::Cookie),
+                    '🥛' | 'm' | 'M' => Ok(Cell::Milk),
+                    c => Err(format!("Unexpected {c:?} in row {}", i + 1)),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::solutions::day12::Cell::{Cookie as C, Empty as E, Milk as M};
+
+    #[test]
+    fn emoji() {
+        let grid = parse(
+            "⬜⬛⬛⬛⬜
+⬜⬛🍪⬛⬜
+⬜🥛🍪⬛⬜
+⬜⬜⬜⬜⬜
+",
+        )
+        .unwrap();
+        assert_eq!(grid, vec![vec![E, E, E], vec![E, C, E], vec![M, C, E]]);
+    }
+
+    #[test]
+    fn compact() {
+        assert_eq!(
+            parse("..../c.m.").unwrap(),
+            vec![vec![E; 4], vec![C, E, M, E]]
+        );
+        assert!(parse("c.x.").is_err());
+    }
+}
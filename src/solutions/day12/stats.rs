@@ -0,0 +1,183 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use itertools::Itertools;
+use serde_json::json;
+
+use super::{analysis::Direction, Board, Cell, Status};
+
+/// How a finished game ended, kept for the leaderboard.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct GameResult {
+    /// `None` for a draw.
+    pub winner: Option<Cell>,
+    /// Names of the players on each team, for those that gave one.
+    pub players: HashMap<Cell, String>,
+    pub moves: usize,
+    /// Direction of the winning line.
+    pub line: Option<Direction>,
+}
+
+impl GameResult {
+    /// The result of `board`, or `None` if it isn't finished.
+    pub fn of(board: &Board) -> Option<Self> {
+        let Status::Winner(winner) = &board.status else {
+            return None;
+        };
+        let winner = (*winner != Cell::Empty).then(|| winner.clone());
+        let line = winner.as_ref().and_then(|winner| {
+            board
+                .lines()
+                .into_iter()
+                .find(|line| line.team == *winner)
+                .map(|line| line.direction)
+        });
+
+        Some(Self {
+            winner,
+            players: board
+                .players
+                .iter()
+                .filter_map(|(team, player)| Some((team.clone(), player.name.clone()?)))
+                .collect(),
+            moves: board.moves.len(),
+            line,
+        })
+    }
+
+    fn outcome(&self, team: &Cell) -> Outcome {
+        match &self.winner {
+            None => Outcome::Draw,
+            Some(winner) if winner == team => Outcome::Win,
+            Some(_) => Outcome::Loss,
+        }
+    }
+}
+
+enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct Record {
+    wins: usize,
+    losses: usize,
+    draws: usize,
+}
+
+impl Record {
+    fn tally(&mut self, outcome: Outcome) -> &mut usize {
+        match outcome {
+            Outcome::Win => &mut self.wins,
+            Outcome::Loss => &mut self.losses,
+            Outcome::Draw => &mut self.draws,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.wins + self.losses + self.draws == 0
+    }
+}
+
+/// Running totals over every recorded result, so the leaderboard doesn't
+/// need to keep the results themselves. Results can be taken back out with
+/// [`Summary::remove`] when a finished game is undone.
+#[derive(Debug, Default)]
+pub struct Summary {
+    games: usize,
+    teams: HashMap<Cell, Record>,
+    players: HashMap<String, Record>,
+    moves: usize,
+    lines: HashMap<Direction, usize>,
+}
+
+impl Summary {
+    pub fn add(&mut self, result: &GameResult) {
+        self.games += 1;
+        self.moves += result.moves;
+        if let Some(line) = result.line {
+            *self.lines.entry(line).or_default() += 1;
+        }
+        for team in [Cell::Cookie, Cell::Milk] {
+            *self
+                .teams
+                .entry(team.clone())
+                .or_default()
+                .tally(result.outcome(&team)) += 1;
+            if let Some(name) = result.players.get(&team) {
+                *self
+                    .players
+                    .entry(name.clone())
+                    .or_default()
+                    .tally(result.outcome(&team)) += 1;
+            }
+        }
+    }
+
+    /// Takes back a result previously passed to [`Summary::add`].
+    pub fn remove(&mut self, result: &GameResult) {
+        self.games = self.games.saturating_sub(1);
+        self.moves = self.moves.saturating_sub(result.moves);
+        if let Some(line) = result.line {
+            if let Some(count) = self.lines.get_mut(&line) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    self.lines.remove(&line);
+                }
+            }
+        }
+        for team in [Cell::Cookie, Cell::Milk] {
+            if let Some(record) = self.teams.get_mut(&team) {
+                let tally = record.tally(result.outcome(&team));
+                *tally = tally.saturating_sub(1);
+            }
+            let Some(name) = result.players.get(&team) else {
+                continue;
+            };
+            if let Some(record) = self.players.get_mut(name) {
+                let tally = record.tally(result.outcome(&team));
+                *tally = tally.saturating_sub(1);
+                if record.is_empty() {
+                    self.players.remove(name);
+                }
+            }
+        }
+    }
+
+    /// Win/loss/draw records per team and per named player, best players
+    /// first, along with game lengths and the kinds of line games were won
+    /// with.
+    pub fn to_json(&self) -> serde_json::Value {
+        let teams = self
+            .teams
+            .iter()
+            .filter(|(_, record)| !record.is_empty())
+            .map(|(team, record)| (team.name(), record))
+            .collect::<HashMap<_, _>>();
+
+        let leaderboard = self
+            .players
+            .iter()
+            .sorted_by_key(|(name, record)| (Reverse(record.wins), record.losses, *name))
+            .map(|(name, record)| json!({ "name": name, "record": record }))
+            .collect_vec();
+
+        let most_common_line = self
+            .lines
+            .iter()
+            .max_by_key(|(direction, count)| (**count, Reverse(**direction)))
+            .map(|(direction, _)| *direction);
+
+        let average_moves = (self.games > 0).then(|| self.moves as f64 / self.games as f64);
+
+        json!({
+            "games": self.games,
+            "teams": teams,
+            "players": leaderboard,
+            "average_moves": average_moves,
+            "lines": self.lines,
+            "most_common_line": most_common_line,
+        })
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, time::Duration};
+
+use sqlx::{
+    types::{uuid::Uuid, Json},
+    PgPool,
+};
+
+use super::{stats::GameResult, Board, BoardConfig, Cell, Move, Player, Status};
+
+/// The default game has no id of its own, so it is stored under the nil id.
+pub const DEFAULT_GAME_ID: Uuid = Uuid::nil();
+
+struct GameRow {
+    id: Uuid,
+    config: Json<BoardConfig>,
+    grid: Json<Vec<Vec<Cell>>>,
+    status: Json<Status>,
+    moves: Json<Vec<Move>>,
+    players: Json<HashMap<Cell, Player>>,
+    next: Option<Json<Cell>>,
+}
+
+/// Loads every game touched within `idle_timeout`, dropping the ones that
+/// weren't, apart from the default game, which is never evicted.
+pub async fn load(
+    pool: &PgPool,
+    idle_timeout: Duration,
+) -> Result<Vec<(Uuid, Board)>, sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM games WHERE updated_at < now() - make_interval(secs => $1) AND id <> $2",
+        idle_timeout.as_secs_f64(),
+        DEFAULT_GAME_ID
+    )
+    .execute(pool)
+    .await?;
+
+    let rows = sqlx::query_as!(
+        GameRow,
+        r#"SELECT
+			id,
+			config AS "config: Json<BoardConfig>",
+			grid AS "grid: Json<Vec<Vec<Cell>>>",
+			status AS "status: Json<Status>",
+			moves AS "moves: Json<Vec<Move>>",
+			players AS "players: Json<HashMap<Cell, Player>>",
+			next AS "next: Json<Cell>"
+		FROM games"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            // Games saved before turns were stored follow on from the last move
+            let next = row
+                .next
+                .map(|next| next.0)
+                .or_else(|| row.moves.last().map(|last| last.team.opponent()));
+            let board = Board {
+                grid: row.grid.0,
+                status: row.status.0,
+                config: row.config.0,
+                next,
+                players: row.players.0,
+                moves: row.moves.0,
+            };
+            (row.id, board)
+        })
+        .collect())
+}
+
+pub async fn save(pool: &PgPool, id: Uuid, board: &Board) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO games (id, config, grid, status, moves, players, next)
+		VALUES ($1, $2, $3, $4, $5, $6, $7)
+		ON CONFLICT (id) DO UPDATE
+		SET
+			config = EXCLUDED.config,
+			grid = EXCLUDED.grid,
+			status = EXCLUDED.status,
+			moves = EXCLUDED.moves,
+			players = EXCLUDED.players,
+			next = EXCLUDED.next,
+			updated_at = CURRENT_TIMESTAMP",
+        id,
+        Json(&board.config) as _,
+        Json(&board.grid) as _,
+        Json(&board.status) as _,
+        Json(&board.moves) as _,
+        Json(&board.players) as _,
+        board.next.as_ref().map(Json) as _,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete(pool: &PgPool, ids: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM games WHERE id = ANY($1)", ids)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Every recorded result, oldest first, along with the game it came from.
+pub async fn load_results(pool: &PgPool) -> Result<Vec<(Option<Uuid>, GameResult)>, sqlx::Error> {
+    let results = sqlx::query!(
+        r#"SELECT game_id, result AS "result: Json<GameResult>" FROM game_results ORDER BY id"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(results
+        .into_iter()
+        .map(|row| (row.game_id, row.result.0))
+        .collect())
+}
+
+pub async fn save_result(pool: &PgPool, id: Uuid, result: &GameResult) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO game_results (game_id, result) VALUES ($1, $2)",
+        id,
+        Json(result) as _
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the latest result recorded for game `id`.
+pub async fn delete_result(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM game_results WHERE id = (SELECT max(id) FROM game_results WHERE game_id = $1)",
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
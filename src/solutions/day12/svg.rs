@@ -0,0 +1,92 @@
+use std::fmt::Display;
+
+use super::{Board, Cell, Status};
+
+/// Side of one grid cell, and of the frame around the grid, in pixels.
+const CELL: usize = 40;
+const PIECE_RADIUS: usize = 16;
+
+const FRAME: &str = "#e0e0e0";
+const EMPTY: &str = "#202020";
+const HIGHLIGHT: &str = "#ffd700";
+
+/// An `image/svg+xml` rendering of a board, laid out like its emoji text: a
+/// frame on the sides and bottom, and the result underneath once finished.
+/// Winning lines are struck through.
+pub struct Svg<'a>(pub &'a Board);
+
+impl Svg<'_> {
+    /// Pixel coordinates of the center of a grid cell.
+    fn center((row, col): (usize, usize)) -> (usize, usize) {
+        ((col + 1) * CELL + CELL / 2, row * CELL + CELL / 2)
+    }
+}
+
+impl Display for Svg<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let board = self.0;
+        let (width, height) = (board.width(), board.height());
+        let framed_width = (width + 2) * CELL;
+        let framed_height = (height + 1) * CELL;
+        let caption = match &board.status {
+            Status::InProgress => None,
+            Status::Winner(Cell::Cookie) => Some("Cookie wins!"),
+            Status::Winner(Cell::Milk) => Some("Milk wins!"),
+            Status::Winner(Cell::Empty) => Some("No winner."),
+        };
+        let total_height = framed_height + caption.map_or(0, |_| CELL);
+
+        writeln!(
+            f,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{framed_width}" height="{total_height}" viewBox="0 0 {framed_width} {total_height}">"#
+        )?;
+        writeln!(
+            f,
+            r#"<rect width="{framed_width}" height="{framed_height}" fill="{FRAME}"/>"#
+        )?;
+        writeln!(
+            f,
+            r#"<rect x="{CELL}" width="{}" height="{}" fill="{EMPTY}"/>"#,
+            width * CELL,
+            height * CELL,
+        )?;
+
+        for (row, cells) in board.grid.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                let (fill, stroke) = match cell {
+                    Cell::Empty => continue,
+                    Cell::Cookie => ("#c68642", "#8b5a2b"),
+                    Cell::Milk => ("#f8f8f8", "#9ec5e8"),
+                };
+                let (cx, cy) = Self::center((row, col));
+                writeln!(
+                    f,
+                    r#"<circle class="{}" cx="{cx}" cy="{cy}" r="{PIECE_RADIUS}" fill="{fill}" stroke="{stroke}" stroke-width="3"/>"#,
+                    cell.name(),
+                )?;
+            }
+        }
+
+        if let Status::Winner(winner) = &board.status {
+            for line in board.lines().iter().filter(|line| line.team == *winner) {
+                let (x1, y1) = Self::center(line.start);
+                let (x2, y2) = Self::center(line.end());
+                writeln!(
+                    f,
+                    r#"<line x1="{x1}" y1="{y1}" x2="{x2}" y2="{y2}" stroke="{HIGHLIGHT}" stroke-width="6" stroke-linecap="round" opacity="0.8"/>"#
+                )?;
+            }
+        }
+
+        if let Some(caption) = caption {
+            writeln!(
+                f,
+                r#"<text x="{}" y="{}" text-anchor="middle" dominant-baseline="middle" font-family="sans-serif" font-size="20">{caption}</text>"#,
+                framed_width / 2,
+                framed_height + CELL / 2,
+            )?;
+        }
+
+        writeln!(f, "</svg>")
+    }
+}